@@ -1,12 +1,21 @@
 // NOTE: This file is just for debugging
 mod gb;
 
-use self::gb::cartridge::Cartridge;
+use self::gb::apu::Channel;
+use self::gb::archive;
+use self::gb::cartridge::{Cartridge, RtcMode};
 use self::gb::cpu::Cpu;
+use self::gb::gbs::{self, Gbs};
 use self::gb::mmu::Mmu;
-use self::gb::ppu::Ppu;
+use self::gb::patch;
+use self::gb::ppu::{Ppu, RenderMode};
 use self::gb::timer::Timer;
+use self::gb::GameBoy;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Battery-backed RAM is flushed to disk at most once per emulated second
+const SAVE_INTERVAL: u32 = 4_194_304;
 
 fn main() {
     let mut cpu = Cpu::new();
@@ -16,36 +25,228 @@ fn main() {
     cpu.simulate_bootloader();
     mmu.simulate_bootloader();
 
-    match load_rom_from_first_arg() {
-        Ok(cart) => mmu.load_cartridge(cart),
+    let mut args: Vec<String> = std::env::args().collect();
+    // Lets the CPU reach VRAM and OAM in any PPU mode, e.g. to tell access timing bugs apart
+    let access_restricted = !take_flag(&mut args, "--no-access-restrictions");
+    let render_mode = if take_flag(&mut args, "--scanline-renderer") {
+        RenderMode::Scanline
+    } else {
+        RenderMode::PixelFifo
+    };
+    mmu.set_access_restricted(access_restricted);
+    ppu.set_render_mode(render_mode);
+
+    let new_gameboy = || {
+        let mut gameboy = GameBoy::new();
+        gameboy.set_access_restricted(access_restricted);
+        gameboy.set_render_mode(render_mode);
+        gameboy
+    };
+    let result =
+        take_headless_options(&mut args).and_then(|headless| match (load_rom_from_first_arg(&mut args)?, headless) {
+            (Rom::Cartridge(cart, save_path), None) => Ok(Some((cart, save_path))),
+            (Rom::Cartridge(cart, _), Some(headless)) => {
+                let mut gameboy = new_gameboy();
+                gameboy.load(cart);
+                run_headless(gameboy, headless).map(|_| None)
+            }
+            (Rom::Gbs(gbs), Some(headless)) => {
+                let song = headless.track.unwrap_or(gbs.first_song);
+                let mut gameboy = new_gameboy();
+                gameboy.load_gbs(&gbs, song).map_err(|err| err.to_string())?;
+                run_headless(gameboy, headless).map(|_| None)
+            }
+            (Rom::Gbs(_), None) => {
+                Err("GBS files can only be rendered, e.g. with --record-audio out.wav --frames 3600".to_owned())
+            }
+        });
+    let save_path = match result {
+        Ok(Some((cart, save_path))) => {
+            mmu.load_cartridge(cart);
+            save_path
+        }
+        Ok(None) => return,
         Err(err) => {
             writeln!(std::io::stderr(), "{}", err.to_string()).unwrap();
             std::process::exit(1);
         }
-    }
+    };
 
+    let mut save_cycles: u32 = 0;
     loop {
-        let cycle = cpu.step(&mut mmu);
+        let cycle = cpu.step(&mut mmu.cpu_bus());
+        if mmu.take_stat_write() {
+            ppu.on_stat_write(&mut mmu);
+        }
         ppu.step(&mut mmu, cycle);
         timer.step(&mut mmu, cycle);
+        mmu.apu_mut().step(cycle);
+        mmu.step(cycle);
+
+        save_cycles += cycle as u32;
+        if save_cycles >= SAVE_INTERVAL {
+            save_cycles -= SAVE_INTERVAL;
+            flush_save_file(mmu.cartridge_mut(), &save_path);
+        }
 
         // TODO: Insert debug code here
     }
 }
 
-fn load_rom_from_first_arg() -> Result<Cartridge, String> {
+// Options to run a fixed number of frames without the debug loop, e.g. "--record-audio out.wav --frames 600"
+struct Headless {
+    frames: u32,
+    record_path: Option<PathBuf>,
+    // Only this channel is recorded, e.g. "--solo wave"
+    solo: Option<Channel>,
+    // 0-based song of a GBS file, given 1-based as "--track 3"
+    track: Option<u8>,
+}
+
+fn take_headless_options(args: &mut Vec<String>) -> Result<Option<Headless>, String> {
+    let record_path = take_option(args, "--record-audio")?.map(PathBuf::from);
+    let solo = match take_option(args, "--solo")?.as_deref() {
+        Some("square1") => Some(Channel::Square1),
+        Some("square2") => Some(Channel::Square2),
+        Some("wave") => Some(Channel::Wave),
+        Some("noise") => Some(Channel::Noise),
+        Some(name) => return Err(format!("unknown channel: {}", name)),
+        None => None,
+    };
+    let track = match take_option(args, "--track")? {
+        Some(track) => match track.parse::<u8>() {
+            Ok(n) if n > 0 => Some(n - 1),
+            _ => return Err(format!("invalid track: {}", track)),
+        },
+        None => None,
+    };
+    let frames = match take_option(args, "--frames")? {
+        Some(frames) => frames.parse().map_err(|_| format!("invalid frame count: {}", frames))?,
+        None if record_path.is_some() || solo.is_some() || track.is_some() => {
+            return Err("--record-audio, --solo and --track require --frames".to_owned())
+        }
+        None => return Ok(None),
+    };
+
+    Ok(Some(Headless {
+        frames,
+        record_path,
+        solo,
+        track,
+    }))
+}
+
+fn run_headless(mut gameboy: GameBoy, headless: Headless) -> Result<(), String> {
+    if headless.record_path.is_some() {
+        gameboy.start_recording();
+    }
+    gameboy.set_solo_channel(headless.solo);
+
+    gameboy.unpause();
+    for _ in 0..headless.frames {
+        gameboy.step();
+        // Nothing plays the samples back, so they are dropped once recorded
+        gameboy.take_samples();
+    }
+
+    if let (Some(path), Some(wav)) = (headless.record_path, gameboy.stop_recording()) {
+        std::fs::write(&path, wav).map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    Ok(())
+}
+
+enum Rom {
+    Cartridge(Cartridge, PathBuf),
+    Gbs(Gbs),
+}
+
+fn load_rom_from_first_arg(args: &mut Vec<String>) -> Result<Rom, String> {
     use std::fs::File;
     use std::io::{BufReader, Read};
 
-    let args: Vec<String> = std::env::args().collect();
+    let entry = take_option(args, "--entry")?;
     if args.len() == 1 {
         return Err("You must specify a ROM file".to_owned());
     };
 
-    let f = File::open(&args[1]).unwrap();
+    let f = File::open(&args[1]).map_err(|err| err.to_string())?;
     let mut buf = BufReader::new(f);
     let mut rom = vec![];
-    buf.read_to_end(&mut rom).unwrap();
+    buf.read_to_end(&mut rom).map_err(|err| err.to_string())?;
+
+    // ROMs can also be loaded from zip and gzip archives
+    let mut rom = archive::extract_rom(rom, entry.as_deref()).map_err(|err| format!("{}: {}", args[1], err))?;
+
+    // An IPS, UPS or BPS patch can be given as the second argument
+    let patch_path = args.get(2);
+    if let Some(path) = patch_path {
+        let data = std::fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+        rom = patch::apply(&rom, &data).map_err(|err| format!("{}: {}", path, err))?;
+    }
+
+    if gbs::is_gbs(&rom) {
+        let gbs = Gbs::parse(&rom).map_err(|err| format!("{}: {}", args[1], err))?;
+        eprintln!("{}", gbs);
+        return Ok(Rom::Gbs(gbs));
+    }
+
+    let mut cart = Cartridge::new(rom).map_err(|err| err.to_string())?;
+    eprintln!("{}", cart.header());
+    cart.set_rtc_mode(RtcMode::Host(host_time));
+
+    // Save files live next to the ROM, e.g. "game.gb" -> "game.sav", or next to the patch
+    // when there is one, since patched games may not share the original's save layout
+    let save_path = Path::new(patch_path.unwrap_or(&args[1])).with_extension("sav");
+    if let Ok(data) = std::fs::read(&save_path) {
+        cart.import_ram(&data);
+    }
+
+    Ok(Rom::Cartridge(cart, save_path))
+}
+
+// Removes "--name value" from the arguments and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let i = match args.iter().position(|arg| arg == name) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    if i + 1 >= args.len() {
+        return Err(format!("{} requires a value", name));
+    }
+
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
+}
+
+// Removes "--name" from the arguments and returns whether it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+fn flush_save_file(cart: &mut Cartridge, path: &Path) {
+    if !cart.is_ram_dirty() {
+        return;
+    }
+
+    if let Some(data) = cart.export_ram() {
+        if let Err(err) = std::fs::write(path, data) {
+            eprintln!("failed to write {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn host_time() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
 
-    Ok(Cartridge::new(rom))
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
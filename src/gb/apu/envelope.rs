@@ -0,0 +1,60 @@
+// Volume envelope of NRx2, shared by the square and noise channels
+pub struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.initial_volume = data >> 4;
+        self.increase = data & 0x08 != 0;
+        self.period = data & 0x07;
+    }
+
+    // The DAC is powered as long as the upper 5 bits of NRx2 are not all zero
+    pub fn is_dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return;
+        }
+        self.timer = self.period;
+
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
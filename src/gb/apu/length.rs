@@ -0,0 +1,53 @@
+// Length counter, which silences its channel once it runs out
+pub struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Self {
+        Length {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    // Returns true when the counter has just run out
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    // `first_half` tells whether the next frame sequencer step leaves the counter alone, in
+    // which case enabling it clocks it right away. Returns true if that made it run out.
+    pub fn set_enabled(&mut self, enabled: bool, first_half: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        if !was_enabled && enabled && first_half {
+            return self.clock();
+        }
+        false
+    }
+
+    pub fn trigger(&mut self, first_half: bool) {
+        if self.counter != 0 {
+            return;
+        }
+
+        self.counter = self.max;
+        if self.enabled && first_half {
+            self.counter -= 1;
+        }
+    }
+}
@@ -0,0 +1,538 @@
+mod envelope;
+mod length;
+mod noise;
+mod resampler;
+mod square;
+mod sweep;
+mod wave;
+
+use self::noise::Noise;
+use self::resampler::Resampler;
+use self::square::Square;
+use self::wave::Wave;
+
+const NR10_ADDR: u16 = 0xFF10;
+const NR20_ADDR: u16 = 0xFF15;
+const NR30_ADDR: u16 = 0xFF1A;
+const NR40_ADDR: u16 = 0xFF1F;
+const NR50_ADDR: u16 = 0xFF24;
+const NR51_ADDR: u16 = 0xFF25;
+const NR52_ADDR: u16 = 0xFF26;
+const WAVE_RAM_ADDR: u16 = 0xFF30;
+
+// Bits that always read back as 1, for 0xFF10..=0xFF2F
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_PERIOD: u16 = 8192;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+pub struct Apu {
+    powered: bool,
+    registers: [u8; 0x20],
+
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+
+    frame_cycles: u16,
+    // Next step of the frame sequencer to be executed
+    frame_step: u8,
+
+    // Only present once a sample rate has been chosen
+    resampler: Option<Resampler>,
+    // Cycles elapsed since the start of the current video frame
+    frame_time: u32,
+    last_output: (f32, f32),
+
+    // Debugging controls, which only apply to the mixed output
+    muted: [bool; 4],
+    solo: Option<Channel>,
+
+    // Output of each channel before mixing, only present when requested
+    channel_resampler: Option<Resampler>,
+    last_channel_outputs: [f32; 4],
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            powered: false,
+            registers: [0x00; 0x20],
+
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+
+            frame_cycles: 0,
+            frame_step: 0,
+
+            resampler: None,
+            frame_time: 0,
+            last_output: (0.0, 0.0),
+
+            muted: [false; 4],
+            solo: None,
+
+            channel_resampler: None,
+            last_channel_outputs: [0.0; 4],
+        }
+    }
+
+    // Resets the emulated hardware, but keeps the output settings
+    fn reset(&mut self) {
+        self.powered = false;
+        self.registers = [0x00; 0x20];
+        self.square1 = Square::new(true);
+        self.square2 = Square::new(false);
+        self.wave = Wave::new();
+        self.noise = Noise::new();
+        self.frame_cycles = 0;
+        self.frame_step = 0;
+    }
+
+    // Register values left behind by the boot ROM, once its chime has finished playing
+    pub fn simulate_bootloader(&mut self) {
+        self.reset();
+        self.write(NR52_ADDR, 0x80);
+        for &(addr, data) in &[
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            // The boot beep has faded out by now, but channel 1 is still on
+            (0xFF12, 0x08),
+            (0xFF14, 0x80),
+            (0xFF12, 0xF3),
+            (0xFF14, 0x3F),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF19, 0x3F),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1E, 0x3F),
+            (0xFF20, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0x3F),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+        ] {
+            self.write(addr, data);
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR52_ADDR => {
+                let channels = [
+                    self.square1.is_enabled(),
+                    self.square2.is_enabled(),
+                    self.wave.is_enabled(),
+                    self.noise.is_enabled(),
+                ];
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0x00, |acc, (i, &on)| acc | (on as u8) << i);
+                0x70 | (self.powered as u8) << 7 | status
+            }
+            NR10_ADDR..=0xFF2F => {
+                let i = (addr - NR10_ADDR) as usize;
+                self.registers[i] | READ_MASKS[i]
+            }
+            WAVE_RAM_ADDR..=0xFF3F => self.wave.read_ram((addr - WAVE_RAM_ADDR) as usize),
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            NR52_ADDR => self.write_power(data & 0x80 != 0),
+            WAVE_RAM_ADDR..=0xFF3F => self.wave.write_ram((addr - WAVE_RAM_ADDR) as usize, data),
+            NR10_ADDR..=0xFF2F => {
+                if !self.powered {
+                    // Length counters stay writable while the APU is off
+                    match addr {
+                        0xFF11 => self.square1.write_length(data),
+                        0xFF16 => self.square2.write_length(data),
+                        0xFF1B => self.wave.write_length(data),
+                        0xFF20 => self.noise.write_length(data),
+                        _ => (),
+                    };
+                    return;
+                }
+
+                self.registers[(addr - NR10_ADDR) as usize] = data;
+
+                // Enabling a length counter has a side effect in the first half of its period
+                let first_half = self.frame_step % 2 == 1;
+                match addr {
+                    NR10_ADDR..=0xFF14 => self.square1.write(addr - NR10_ADDR, data, first_half),
+                    NR20_ADDR..=0xFF19 => self.square2.write(addr - NR20_ADDR, data, first_half),
+                    NR30_ADDR..=0xFF1E => self.wave.write(addr - NR30_ADDR, data, first_half),
+                    NR40_ADDR..=0xFF23 => self.noise.write(addr - NR40_ADDR, data, first_half),
+                    _ => (),
+                };
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn write_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_step = 0;
+            self.frame_cycles = 0;
+        }
+        if !on && self.powered {
+            self.registers = [0x00; 0x20];
+            self.square1.power_off();
+            self.square2.power_off();
+            self.wave.power_off();
+            self.noise.power_off();
+        }
+        self.powered = on;
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.resampler = Some(Resampler::new(rate, 2));
+        if self.channel_resampler.is_some() {
+            self.channel_resampler = Some(Resampler::new(rate, 4));
+        }
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.resampler.as_ref().map(|r| r.sample_rate())
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    // Soloing a channel mutes all the others, regardless of their own setting
+    pub fn set_solo_channel(&mut self, channel: Option<Channel>) {
+        self.solo = channel;
+    }
+
+    fn is_audible(&self, index: usize) -> bool {
+        match self.solo {
+            Some(channel) => channel as usize == index,
+            None => !self.muted[index],
+        }
+    }
+
+    // Only takes effect once a sample rate has been set
+    pub fn set_unmixed_output(&mut self, enabled: bool) {
+        self.channel_resampler = match self.sample_rate() {
+            Some(rate) if enabled => Some(Resampler::new(rate, 4)),
+            _ => None,
+        };
+        self.last_channel_outputs = [0.0; 4];
+    }
+
+    pub fn step(&mut self, cycle: u8) {
+        if self.powered {
+            let cycle = cycle as u16;
+            self.square1.step(cycle);
+            self.square2.step(cycle);
+            self.wave.step(cycle);
+            self.noise.step(cycle);
+
+            self.frame_cycles += cycle;
+            if self.frame_cycles >= FRAME_SEQUENCER_PERIOD {
+                self.frame_cycles -= FRAME_SEQUENCER_PERIOD;
+                self.clock_frame_sequencer();
+            }
+        }
+
+        // Nothing ends the frame without a sample rate, e.g. in the CLI debug loop
+        if self.resampler.is_some() {
+            self.frame_time += cycle as u32;
+            self.update_output();
+        }
+    }
+
+    fn update_output(&mut self) {
+        let outputs = self.channel_outputs();
+
+        let (left, right) = self.mix(&outputs);
+        let (last_left, last_right) = self.last_output;
+        if left != last_left || right != last_right {
+            if let Some(resampler) = self.resampler.as_mut() {
+                resampler.add_delta(self.frame_time, &[left - last_left, right - last_right]);
+            }
+            self.last_output = (left, right);
+        }
+
+        if let Some(resampler) = self.channel_resampler.as_mut() {
+            if outputs != self.last_channel_outputs {
+                let mut deltas = [0.0; 4];
+                for (i, delta) in deltas.iter_mut().enumerate() {
+                    *delta = outputs[i] - self.last_channel_outputs[i];
+                }
+                resampler.add_delta(self.frame_time, &deltas);
+                self.last_channel_outputs = outputs;
+            }
+        }
+    }
+
+    // Makes the samples of the video frame that has just finished available
+    pub fn end_frame(&mut self) {
+        for resampler in self.resampler.iter_mut().chain(self.channel_resampler.iter_mut()) {
+            resampler.end_frame(self.frame_time);
+        }
+        self.frame_time = 0;
+    }
+
+    // Interleaved left and right samples, between -1.0 and 1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        let mut samples = vec![];
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.drain(&mut samples);
+        }
+        samples
+    }
+
+    // Interleaved samples of the four channels, in the order of `Channel`
+    pub fn take_channel_samples(&mut self) -> Vec<f32> {
+        let mut samples = vec![];
+        if let Some(resampler) = self.channel_resampler.as_mut() {
+            resampler.drain(&mut samples);
+        }
+        samples
+    }
+
+    // Length counters are clocked at 256 Hz, the sweep at 128 Hz and envelopes at 64 Hz
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // Current output of each channel's DAC, between -1.0 and 1.0, ignoring mute and solo
+    pub fn channel_outputs(&self) -> [f32; 4] {
+        if !self.powered {
+            return [0.0; 4];
+        }
+
+        let outputs = [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ];
+        let mut analog = [0.0; 4];
+        for (a, output) in analog.iter_mut().zip(outputs.iter()) {
+            // Each DAC maps 0..=15 to a voltage from 1.0 down to -1.0, and outputs nothing while off
+            if let Some(v) = output {
+                *a = 1.0 - *v as f32 / 7.5;
+            }
+        }
+        analog
+    }
+
+    // Current output of the left and right terminals, each between -1.0 and 1.0
+    pub fn output(&self) -> (f32, f32) {
+        self.mix(&self.channel_outputs())
+    }
+
+    fn mix(&self, outputs: &[f32; 4]) -> (f32, f32) {
+        let panning = self.registers[(NR51_ADDR - NR10_ADDR) as usize];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, analog) in outputs.iter().enumerate() {
+            if !self.is_audible(i) {
+                continue;
+            }
+            if panning & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if panning & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+
+        let volume = self.registers[(NR50_ADDR - NR10_ADDR) as usize];
+        let left_volume = (((volume >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((volume & 0x07) + 1) as f32 / 8.0;
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(NR52_ADDR, 0x80);
+        apu
+    }
+
+    #[test]
+    fn test_apu_read_masks() {
+        let mut apu = build_apu();
+        apu.write(0xFF11, 0x95);
+        apu.write(0xFF13, 0x12);
+        assert_eq!(0xBF, apu.read(0xFF11));
+        assert_eq!(0xFF, apu.read(0xFF13));
+        assert_eq!(0xFF, apu.read(0xFF27));
+    }
+
+    #[test]
+    fn test_apu_power_off() {
+        let mut apu = build_apu();
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        apu.write(0xFF24, 0x77);
+        assert_eq!(0xF1, apu.read(NR52_ADDR));
+
+        apu.write(NR52_ADDR, 0x00);
+        assert_eq!(0x70, apu.read(NR52_ADDR));
+        assert_eq!(0x00, apu.read(0xFF24));
+
+        // Only wave RAM and length counters can be written while off
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF30, 0xAB);
+        assert_eq!(0x00, apu.read(0xFF24));
+        assert_eq!(0xAB, apu.read(0xFF30));
+    }
+
+    #[test]
+    fn test_apu_bootloader() {
+        let mut apu = Apu::new();
+        apu.simulate_bootloader();
+        assert_eq!(0xF1, apu.read(NR52_ADDR));
+
+        // Channel 1 is on, but silent
+        let level = apu.channel_outputs()[0];
+        for _ in 0..FRAME_SEQUENCER_PERIOD / 4 * 8 {
+            apu.step(4);
+            assert_eq!(level, apu.channel_outputs()[0]);
+        }
+    }
+
+    #[test]
+    fn test_apu_wave_ram_while_playing() {
+        let mut apu = build_apu();
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1E, 0x80);
+        assert_eq!(0xF4, apu.read(NR52_ADDR));
+
+        // Wave RAM is locked out on the DMG while the channel plays
+        apu.write(0xFF30, 0x34);
+        assert_eq!(0xFF, apu.read(0xFF30));
+
+        apu.write(0xFF1A, 0x00);
+        assert_eq!(0x12, apu.read(0xFF30));
+    }
+
+    #[test]
+    fn test_apu_length_counter() {
+        let mut apu = build_apu();
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF16, 0x3E); // 2 steps left
+        apu.write(0xFF19, 0xC0);
+        assert_eq!(0xF2, apu.read(NR52_ADDR));
+
+        for _ in 0..FRAME_SEQUENCER_PERIOD / 4 {
+            apu.step(4);
+        }
+        assert_eq!(0xF2, apu.read(NR52_ADDR));
+        for _ in 0..FRAME_SEQUENCER_PERIOD / 4 * 2 {
+            apu.step(4);
+        }
+        assert_eq!(0xF0, apu.read(NR52_ADDR));
+    }
+
+    #[test]
+    fn test_apu_sweep_overflow() {
+        let mut apu = build_apu();
+        apu.write(0xFF10, 0x11); // Period 1, shift 1
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x85); // 0x500 + 0x280 does not overflow yet
+        assert_eq!(0xF1, apu.read(NR52_ADDR));
+
+        for _ in 0..FRAME_SEQUENCER_PERIOD / 4 * 3 {
+            apu.step(4);
+        }
+        assert_eq!(0xF0, apu.read(NR52_ADDR));
+    }
+
+    #[test]
+    fn test_apu_mute_and_solo() {
+        let mut apu = build_apu();
+        apu.write(NR51_ADDR, 0xFF);
+        apu.write(NR50_ADDR, 0x77);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x80);
+        assert_eq!((0.5, 0.5), apu.output());
+
+        apu.set_channel_muted(Channel::Square1, true);
+        assert_eq!((0.25, 0.25), apu.output());
+        apu.set_solo_channel(Some(Channel::Noise));
+        assert_eq!((0.0, 0.0), apu.output());
+        assert_eq!([1.0, 1.0, 0.0, 0.0], apu.channel_outputs());
+
+        apu.set_solo_channel(None);
+        apu.set_channel_muted(Channel::Square1, false);
+        assert_eq!((0.5, 0.5), apu.output());
+    }
+
+    #[test]
+    fn test_apu_unmixed_output() {
+        let mut apu = build_apu();
+        apu.set_unmixed_output(true);
+        assert!(apu.take_channel_samples().is_empty());
+
+        apu.set_sample_rate(44_100);
+        apu.set_unmixed_output(true);
+        for _ in 0..70_224 / 4 {
+            apu.step(4);
+        }
+        apu.end_frame();
+        assert_eq!(738 * 4, apu.take_channel_samples().len());
+    }
+
+    #[test]
+    fn test_apu_frame_time_without_output() {
+        let mut apu = build_apu();
+        for _ in 0..1000 {
+            apu.step(0xFF);
+        }
+        assert_eq!(0, apu.frame_time);
+    }
+}
@@ -0,0 +1,153 @@
+use super::envelope::Envelope;
+use super::length::Length;
+
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Channel 4, fed by a linear-feedback shift register
+pub struct Noise {
+    enabled: bool,
+
+    shift: u8,
+    short_mode: bool,
+    divisor: u16,
+    lfsr: u16,
+    timer: u32,
+
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            enabled: false,
+
+            shift: 0,
+            short_mode: false,
+            divisor: DIVISORS[0],
+            lfsr: 0x7FFF,
+            timer: 0,
+
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // `reg` is the offset from NR40, which does not exist
+    pub fn write(&mut self, reg: u16, data: u8, first_half: bool) {
+        match reg {
+            1 => self.length.load((data & 0x3F) as u16),
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => {
+                self.shift = data >> 4;
+                self.short_mode = data & 0x08 != 0;
+                self.divisor = DIVISORS[(data & 0x07) as usize];
+            }
+            4 => {
+                let expired = self.length.set_enabled(data & 0x40 != 0, first_half);
+                if data & 0x80 != 0 {
+                    self.trigger(first_half);
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => (),
+        };
+    }
+
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Noise::new();
+        self.length = length;
+        self.length.set_enabled(false, false);
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    fn trigger(&mut self, first_half: bool) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.length.trigger(first_half);
+        self.envelope.trigger();
+    }
+
+    fn period(&self) -> u32 {
+        (self.divisor as u32) << self.shift
+    }
+
+    pub fn step(&mut self, cycle: u16) {
+        let mut cycle = cycle as u32;
+        while cycle >= self.timer {
+            cycle -= self.timer;
+            self.timer = self.period();
+            // The LFSR is not clocked at all with shifts of 14 and 15
+            if self.shift < 14 {
+                self.clock_lfsr();
+            }
+        }
+        self.timer -= cycle;
+    }
+
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | bit << 14;
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !0x40) | bit << 6;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.is_dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+
+        Some((!self.lfsr & 0x01) as u8 * self.envelope.volume())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_short_mode() {
+        let mut noise = Noise::new();
+        noise.write(2, 0xF0, false);
+        noise.write(3, 0x08, false);
+        noise.write(4, 0x80, false);
+
+        // The 7-bit LFSR repeats every 127 clocks
+        let mut outputs = vec![];
+        for _ in 0..254 {
+            noise.step(8);
+            outputs.push(noise.output());
+        }
+        assert_eq!(outputs[..127], outputs[127..]);
+        assert!(outputs.contains(&Some(15)) && outputs.contains(&Some(0)));
+    }
+}
@@ -0,0 +1,171 @@
+use std::f64::consts::PI;
+
+const CLOCK_RATE: u64 = 4_194_304;
+
+// Sample positions are kept in 32.32 fixed point, so that no fraction of a sample is lost between frames
+const FRAC_BITS: u32 = 32;
+const PHASE_BITS: u32 = 5;
+const PHASES: usize = 1 << PHASE_BITS;
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+
+// Band-limited resampler from the APU clock to an output sample rate, for a fixed number of outputs.
+// Changes in amplitude are added as windowed sinc impulses to a difference buffer, which is
+// integrated back into samples when they are read.
+pub struct Resampler {
+    sample_rate: u32,
+    // Output samples per APU cycle
+    factor: u64,
+    // Position of the first cycle of the current frame
+    offset: u64,
+    kernel: Vec<[f32; WIDTH]>,
+
+    deltas: Vec<Vec<f32>>,
+    integrators: Vec<f32>,
+    // High-pass filter to remove the DC offset, like the capacitors on the real hardware do
+    capacitors: Vec<f32>,
+    charge_factor: f32,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32, outputs: usize) -> Self {
+        let factor = ((sample_rate as u64) << FRAC_BITS) / CLOCK_RATE;
+        Resampler {
+            sample_rate,
+            factor,
+            offset: 0,
+            kernel: build_kernel(),
+
+            deltas: vec![vec![0.0; WIDTH]; outputs],
+            integrators: vec![0.0; outputs],
+            capacitors: vec![0.0; outputs],
+            charge_factor: 0.999_958_f32.powf(CLOCK_RATE as f32 / sample_rate as f32),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // `time` is the number of cycles since the start of the current frame, and `amplitudes` has a
+    // change for each output
+    pub fn add_delta(&mut self, time: u32, amplitudes: &[f32]) {
+        let pos = self.offset + time as u64 * self.factor;
+        let index = (pos >> FRAC_BITS) as usize;
+        let phase = ((pos >> (FRAC_BITS - PHASE_BITS)) as usize) & (PHASES - 1);
+
+        let kernel = &self.kernel[phase];
+        for (deltas, amplitude) in self.deltas.iter_mut().zip(amplitudes) {
+            if *amplitude == 0.0 {
+                continue;
+            }
+            if deltas.len() < index + WIDTH {
+                deltas.resize(index + WIDTH, 0.0);
+            }
+            for (d, k) in deltas[index..index + WIDTH].iter_mut().zip(kernel.iter()) {
+                *d += amplitude * k;
+            }
+        }
+    }
+
+    // Closes the current frame after `time` cycles, making its samples available
+    pub fn end_frame(&mut self, time: u32) {
+        self.offset += time as u64 * self.factor;
+    }
+
+    pub fn samples_available(&self) -> usize {
+        (self.offset >> FRAC_BITS) as usize
+    }
+
+    // Moves the finished samples to `out`, interleaved in the order of the outputs
+    pub fn drain(&mut self, out: &mut Vec<f32>) {
+        let count = self.samples_available();
+        for d in self.deltas.iter_mut() {
+            if d.len() < count + WIDTH {
+                d.resize(count + WIDTH, 0.0);
+            }
+        }
+
+        out.reserve(count * self.deltas.len());
+        for i in 0..count {
+            for j in 0..self.deltas.len() {
+                self.integrators[j] += self.deltas[j][i];
+                let input = self.integrators[j];
+                let output = input - self.capacitors[j];
+                self.capacitors[j] = input - output * self.charge_factor;
+                out.push(output);
+            }
+        }
+
+        for d in self.deltas.iter_mut() {
+            d.drain(..count);
+        }
+        self.offset -= (count as u64) << FRAC_BITS;
+    }
+}
+
+// Windowed sinc impulses for each sub-sample phase, normalized so every phase adds up to 1
+fn build_kernel() -> Vec<[f32; WIDTH]> {
+    // The cutoff is a bit under the Nyquist frequency to keep aliasing down
+    let cutoff = 0.9;
+    (0..PHASES)
+        .map(|phase| {
+            let mut taps = [0.0; WIDTH];
+            let fraction = phase as f64 / PHASES as f64;
+            for (k, tap) in taps.iter_mut().enumerate() {
+                let x = k as f64 - (HALF_WIDTH - 1) as f64 - fraction;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * cutoff * x).sin() / (PI * cutoff * x)
+                };
+                let window =
+                    0.42 + 0.5 * (PI * x / HALF_WIDTH as f64).cos() + 0.08 * (2.0 * PI * x / HALF_WIDTH as f64).cos();
+                *tap = sinc * window;
+            }
+            let sum: f64 = taps.iter().sum();
+            let mut normalized = [0.0; WIDTH];
+            for (n, tap) in normalized.iter_mut().zip(taps.iter()) {
+                *n = (tap / sum) as f32;
+            }
+            normalized
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resampler_frame_locked() {
+        let mut resampler = Resampler::new(44_100, 2);
+        let mut total = 0;
+        for _ in 0..60 {
+            resampler.end_frame(70_224);
+            let mut out = vec![];
+            resampler.drain(&mut out);
+            let count = out.len() / 2;
+            // 738.3 samples per frame
+            assert!(count == 738 || count == 739);
+            total += count;
+        }
+        assert_eq!(44_100 * 70_224 * 60 / CLOCK_RATE as usize, total);
+    }
+
+    #[test]
+    fn test_resampler_step() {
+        let mut resampler = Resampler::new(48_000, 2);
+        resampler.add_delta(1000, &[0.5, -0.5]);
+        resampler.end_frame(70_224);
+        let mut out = vec![];
+        resampler.drain(&mut out);
+
+        // The step settles right after the edge, then slowly decays through the high-pass filter
+        let edge = (1000 * 48_000 / CLOCK_RATE) as usize + HALF_WIDTH;
+        assert_eq!(0.0, out[0]);
+        assert!((out[(edge + HALF_WIDTH) * 2] - 0.5).abs() < 0.05);
+        assert!((out[(edge + HALF_WIDTH) * 2 + 1] + 0.5).abs() < 0.05);
+        assert!(out[out.len() - 2].abs() < 0.1);
+    }
+}
@@ -0,0 +1,149 @@
+use super::envelope::Envelope;
+use super::length::Length;
+use super::sweep::Sweep;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+// Channels 1 and 2, where only channel 1 has a frequency sweep
+pub struct Square {
+    enabled: bool,
+
+    duty: u8,
+    position: usize,
+    frequency: u16,
+    timer: u16,
+
+    length: Length,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Self {
+        Square {
+            enabled: false,
+
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep: if has_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // `reg` is the offset from NRx0
+    pub fn write(&mut self, reg: u16, data: u8, first_half: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    if !sweep.write(data) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = data >> 6;
+                self.length.load((data & 0x3F) as u16);
+            }
+            2 => {
+                self.envelope.write(data);
+                if !self.envelope.is_dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data & 0x07) as u16) << 8;
+                let expired = self.length.set_enabled(data & 0x40 != 0, first_half);
+                if data & 0x80 != 0 {
+                    self.trigger(first_half);
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => (),
+        };
+    }
+
+    // Everything but the length counter is reset when the APU is turned off
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Square::new(self.sweep.is_some());
+        self.length = length;
+        self.length.set_enabled(false, false);
+    }
+
+    // Only the length is writable while the APU is off
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load((data & 0x3F) as u16);
+    }
+
+    fn trigger(&mut self, first_half: bool) {
+        self.enabled = self.envelope.is_dac_enabled();
+        self.timer = self.period();
+        self.length.trigger(first_half);
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 4
+    }
+
+    pub fn step(&mut self, cycle: u16) {
+        let mut cycle = cycle;
+        while cycle >= self.timer {
+            cycle -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 8;
+        }
+        self.timer -= cycle;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.clock(&mut self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    // Digital output from 0 to 15, or None while the DAC is off
+    pub fn output(&self) -> Option<u8> {
+        if !self.envelope.is_dac_enabled() {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+
+        Some(DUTY_PATTERNS[self.duty as usize][self.position] * self.envelope.volume())
+    }
+}
@@ -0,0 +1,97 @@
+// Frequency sweep of channel 1 (NR10)
+pub struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+
+    enabled: bool,
+    timer: u8,
+    shadow: u16,
+    // Clearing the negate bit after it has been used in a calculation disables the channel
+    negate_used: bool,
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+
+            enabled: false,
+            timer: 0,
+            shadow: 0,
+            negate_used: false,
+        }
+    }
+
+    // Returns false if the channel has to be disabled
+    pub fn write(&mut self, data: u8) -> bool {
+        self.period = (data >> 4) & 0x07;
+        self.negate = data & 0x08 != 0;
+        self.shift = data & 0x07;
+
+        !self.negate_used || self.negate
+    }
+
+    // Returns false if the channel has to be disabled
+    pub fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.timer = self.reload_value();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.negate_used = false;
+
+        self.shift == 0 || self.calculate().is_some()
+    }
+
+    // Updates the channel frequency, and returns false if the channel has to be disabled
+    pub fn clock(&mut self, frequency: &mut u16) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer != 0 {
+            return true;
+        }
+        self.timer = self.reload_value();
+
+        if !self.enabled || self.period == 0 {
+            return true;
+        }
+
+        let new_frequency = match self.calculate() {
+            Some(f) => f,
+            None => return false,
+        };
+        if self.shift == 0 {
+            return true;
+        }
+        self.shadow = new_frequency;
+        *frequency = new_frequency;
+
+        // The new frequency is checked for overflow once more, but not used
+        self.calculate().is_some()
+    }
+
+    // A period of 0 is treated as 8
+    fn reload_value(&self) -> u8 {
+        if self.period == 0 {
+            8
+        } else {
+            self.period
+        }
+    }
+
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let frequency = if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+
+        if frequency > 0x7FF {
+            None
+        } else {
+            Some(frequency)
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use super::length::Length;
+
+pub const WAVE_RAM_SIZE: usize = 0x10;
+
+// Channel 3, which plays back 32 4-bit samples from wave RAM
+pub struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+
+    volume_shift: u8,
+    position: usize,
+    frequency: u16,
+    timer: u16,
+
+    length: Length,
+    ram: [u8; WAVE_RAM_SIZE],
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+
+            volume_shift: 4,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+
+            length: Length::new(256),
+            ram: [0x00; WAVE_RAM_SIZE],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // `reg` is the offset from NR30
+    pub fn write(&mut self, reg: u16, data: u8, first_half: bool) {
+        match reg {
+            0 => {
+                self.dac_enabled = data & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(data as u16),
+            // Muted, 100%, 50% and 25%
+            2 => self.volume_shift = [4, 0, 1, 2][((data >> 5) & 0x03) as usize],
+            3 => self.frequency = (self.frequency & 0x0700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data & 0x07) as u16) << 8;
+                let expired = self.length.set_enabled(data & 0x40 != 0, first_half);
+                if data & 0x80 != 0 {
+                    self.trigger(first_half);
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => (),
+        };
+    }
+
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(256));
+        let ram = self.ram;
+        *self = Wave::new();
+        self.length = length;
+        self.length.set_enabled(false, false);
+        self.ram = ram;
+    }
+
+    pub fn write_length(&mut self, data: u8) {
+        self.length.load(data as u16);
+    }
+
+    // While the channel plays, the DMG only lets the CPU through on the exact cycle the channel
+    // fetches a sample, and then only to the byte being played. The APU is stepped an instruction
+    // at a time, so that window is not emulated and wave RAM is locked for the whole time.
+    pub fn read_ram(&self, index: usize) -> u8 {
+        if self.enabled {
+            return 0xFF;
+        }
+        self.ram[index]
+    }
+
+    pub fn write_ram(&mut self, index: usize, data: u8) {
+        if !self.enabled {
+            self.ram[index] = data;
+        }
+    }
+
+    fn trigger(&mut self, first_half: bool) {
+        self.enabled = self.dac_enabled;
+        self.timer = self.period();
+        self.position = 0;
+        self.length.trigger(first_half);
+    }
+
+    fn period(&self) -> u16 {
+        (2048 - self.frequency) * 2
+    }
+
+    pub fn step(&mut self, cycle: u16) {
+        let mut cycle = cycle;
+        while cycle >= self.timer {
+            cycle -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycle;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac_enabled {
+            return None;
+        }
+        if !self.enabled {
+            return Some(0);
+        }
+
+        // The upper nibble is played first
+        let byte = self.ram[self.position / 2];
+        let sample = if self.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        Some(sample >> self.volume_shift)
+    }
+}
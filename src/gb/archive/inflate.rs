@@ -0,0 +1,254 @@
+// DEFLATE decompressor (RFC 1951), enough for zip and gzip ROM archives
+
+const MAX_BITS: usize = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// Order in which the code lengths of the code length alphabet are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Returns None if the stream is corrupted or ends early. Decompression stops soon after the output
+// grows past `limit`, so a stream that is too large comes back longer than `limit`.
+pub fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = vec![];
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored_block(&mut reader, &mut out)?,
+            1 => {
+                let (literals, distances) = fixed_codes();
+                compressed_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                compressed_block(&mut reader, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return None,
+        };
+
+        if last || out.len() > limit {
+            return Some(out);
+        }
+    }
+}
+
+// At most 64 KiB, so the limit is checked once the block is done
+fn stored_block(reader: &mut BitReader, out: &mut Vec<u8>) -> Option<()> {
+    reader.align();
+    let len = reader.bytes(2)?;
+    let nlen = reader.bytes(2)?;
+    if len != !nlen & 0xFFFF {
+        return None;
+    }
+
+    for _ in 0..len {
+        out.push(reader.bytes(1)? as u8);
+    }
+    Some(())
+}
+
+fn compressed_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+    limit: usize,
+) -> Option<()> {
+    while out.len() <= limit {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let i = symbol - 257;
+                let length = *LENGTH_BASE.get(i)? as usize + reader.bits(LENGTH_EXTRA[i])? as usize;
+
+                let i = distances.decode(reader)? as usize;
+                let distance = *DISTANCE_BASE.get(i)? as usize + reader.bits(DISTANCE_EXTRA[i])? as usize;
+                if distance > out.len() {
+                    return None;
+                }
+
+                // Copied byte by byte since the match may overlap with its own output
+                let start = out.len() - distance;
+                for j in 0..length {
+                    out.push(out[start + j]);
+                }
+            }
+        };
+    }
+    Some(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    // Literal and distance code lengths are run-length encoded as a single sequence
+    let mut lengths = vec![];
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return None,
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != literal_count + distance_count {
+        return None;
+    }
+
+    let (literals, distances) = lengths.split_at(literal_count);
+    Some((Huffman::new(literals), Huffman::new(distances)))
+}
+
+// Canonical Huffman code, stored as the number of codes of each length and the symbols
+// sorted by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&s| lengths[s as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, bit: 0 }
+    }
+
+    // Bits are packed starting from the least significant one
+    fn bits(&mut self, count: u8) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Some(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    // Little-endian bytes, only valid when aligned
+    fn bytes(&mut self, count: u8) -> Option<u32> {
+        self.bits(count * 8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflate_stored() {
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(Some(b"abc".to_vec()), inflate(&data, 0x100));
+    }
+
+    #[test]
+    fn test_inflate_fixed() {
+        // "hello hello hello" as compressed by zlib
+        let data = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x00];
+        assert_eq!(Some(b"hello hello hello".to_vec()), inflate(&data, 0x100));
+        assert_eq!(None, inflate(&data[..4], 0x100));
+        assert_eq!(Some(b"hello ".to_vec()), inflate(&data, 5));
+    }
+
+    #[test]
+    fn test_inflate_dynamic() {
+        let data = [
+            0x25, 0x8A, 0x81, 0x09, 0x00, 0x30, 0x0C, 0xC2, 0x6E, 0x4D, 0xF4, 0xFF, 0x1B, 0xD6, 0x76, 0x20, 0x28, 0x31,
+            0x4A, 0x91, 0x89, 0x64, 0x8B, 0x3F, 0x0A, 0xA9, 0xDD, 0xC7, 0xE3, 0x55, 0xC7, 0x4C, 0x4F, 0x29, 0x91, 0x07,
+        ];
+        assert_eq!(
+            Some(b"bbadabaababacaabaaabacaadaacdbdbaabbcaabadbbbdabcdbaaabdacba".to_vec()),
+            inflate(&data, 0x100)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+mod inflate;
+
+use self::inflate::inflate;
+use super::crc::crc32;
+use std::error;
+use std::fmt;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_LOCAL_HEADER: u32 = 0x04034B50;
+const ZIP_CENTRAL_HEADER: u32 = 0x02014B50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x06054B50;
+const ZIP_END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+// The largest Game Boy ROMs are 8 MiB, and anything bigger is probably a zip bomb
+const MAX_ROM_SIZE: usize = 8 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    Corrupted,
+    UnsupportedCompression(u16),
+    ChecksumMismatch,
+    TooLarge,
+    NoRom,
+    EntryNotFound(String),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ArchiveError::*;
+
+        match *self {
+            Corrupted => write!(f, "archive is corrupted"),
+            UnsupportedCompression(method) => write!(f, "unsupported compression method in archive: {}", method),
+            ChecksumMismatch => write!(f, "archive is corrupted (checksum mismatch)"),
+            TooLarge => write!(f, "file in the archive is too large for a ROM"),
+            NoRom => write!(f, "no .gb or .gbc file found in the archive"),
+            EntryNotFound(ref name) => write!(f, "no file named {} in the archive", name),
+        }
+    }
+}
+
+impl error::Error for ArchiveError {}
+
+// Unpacks a ROM from a zip or gzip archive. From zip archives, the entry with the given name is
+// picked, or the first .gb/.gbc one. Anything else is assumed to be a ROM already.
+pub fn extract_rom(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    if data.starts_with(&GZIP_MAGIC) {
+        extract_gzip(&data)
+    } else if read_le32(&data, 0) == Ok(ZIP_LOCAL_HEADER) {
+        extract_zip(&data, entry)
+    } else {
+        Ok(data)
+    }
+}
+
+fn extract_gzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let method = *data.get(2).ok_or(ArchiveError::Corrupted)?;
+    if method != METHOD_DEFLATE as u8 {
+        return Err(ArchiveError::UnsupportedCompression(method as u16));
+    }
+    let flags = *data.get(3).ok_or(ArchiveError::Corrupted)?;
+
+    // Skip the optional extra field, file name, comment and header checksum
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        pos += 2 + read_le16(data, pos)? as usize;
+    }
+    for &flag in &[0x08, 0x10] {
+        if flags & flag != 0 {
+            let len = data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0));
+            pos += len.ok_or(ArchiveError::Corrupted)? + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+
+    if data.len() < pos + 8 {
+        return Err(ArchiveError::Corrupted);
+    }
+    let trailer = data.len() - 8;
+    let out = inflate_rom(&data[pos..trailer])?;
+
+    if crc32(&out) != read_le32(data, trailer)? || out.len() as u32 != read_le32(data, trailer + 4)? {
+        return Err(ArchiveError::ChecksumMismatch);
+    }
+    Ok(out)
+}
+
+struct ZipEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    local_header: usize,
+}
+
+fn extract_zip(data: &[u8], name: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let entries = read_central_directory(data)?;
+
+    let entry = match name {
+        Some(name) => entries
+            .iter()
+            .find(|e| e.name == name || e.name.rsplit('/').next() == Some(name))
+            .ok_or_else(|| ArchiveError::EntryNotFound(name.to_owned()))?,
+        None => entries
+            .iter()
+            .find(|e| is_rom_name(&e.name))
+            .ok_or(ArchiveError::NoRom)?,
+    };
+
+    // Sizes in the local header may be left out, so only the variable lengths are taken from it
+    let header = entry.local_header;
+    if read_le32(data, header)? != ZIP_LOCAL_HEADER {
+        return Err(ArchiveError::Corrupted);
+    }
+    let start = offset(header, 30)?;
+    let start = offset(start, read_le16(data, offset(header, 26)?)? as usize)?;
+    let start = offset(start, read_le16(data, offset(header, 28)?)? as usize)?;
+    let compressed = data
+        .get(start..offset(start, entry.compressed_size)?)
+        .ok_or(ArchiveError::Corrupted)?;
+
+    let out = match entry.method {
+        METHOD_STORED if compressed.len() > MAX_ROM_SIZE => return Err(ArchiveError::TooLarge),
+        METHOD_STORED => compressed.to_vec(),
+        METHOD_DEFLATE => inflate_rom(compressed)?,
+        method => return Err(ArchiveError::UnsupportedCompression(method)),
+    };
+    if crc32(&out) != entry.crc {
+        return Err(ArchiveError::ChecksumMismatch);
+    }
+    Ok(out)
+}
+
+fn inflate_rom(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let out = inflate(data, MAX_ROM_SIZE).ok_or(ArchiveError::Corrupted)?;
+    if out.len() > MAX_ROM_SIZE {
+        return Err(ArchiveError::TooLarge);
+    }
+    Ok(out)
+}
+
+fn read_central_directory(data: &[u8]) -> Result<Vec<ZipEntry>, ArchiveError> {
+    // The end record sits at the very end, followed only by a comment of up to 64 KiB
+    let end = (0..=data.len().saturating_sub(ZIP_END_OF_CENTRAL_DIRECTORY_SIZE))
+        .rev()
+        .take(0x10000)
+        .find(|&i| read_le32(data, i) == Ok(ZIP_END_OF_CENTRAL_DIRECTORY))
+        .ok_or(ArchiveError::Corrupted)?;
+
+    let count = read_le16(data, offset(end, 10)?)? as usize;
+    let mut pos = read_le32(data, offset(end, 16)?)? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if read_le32(data, pos)? != ZIP_CENTRAL_HEADER {
+            return Err(ArchiveError::Corrupted);
+        }
+
+        // The fixed part of the header is checked in one go, after which `pos + 46` cannot overflow
+        if data.len() < offset(pos, 46)? {
+            return Err(ArchiveError::Corrupted);
+        }
+        let name_len = read_le16(data, pos + 28)? as usize;
+        let name = data
+            .get(pos + 46..offset(pos + 46, name_len)?)
+            .ok_or(ArchiveError::Corrupted)?;
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: read_le16(data, pos + 10)?,
+            crc: read_le32(data, pos + 16)?,
+            compressed_size: read_le32(data, pos + 20)? as usize,
+            local_header: read_le32(data, pos + 42)? as usize,
+        });
+
+        pos += 46 + name_len + read_le16(data, pos + 30)? as usize + read_le16(data, pos + 32)? as usize;
+    }
+    Ok(entries)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+// Positions come from the archive itself, and may overflow on 32-bit targets
+fn offset(pos: usize, len: usize) -> Result<usize, ArchiveError> {
+    pos.checked_add(len).ok_or(ArchiveError::Corrupted)
+}
+
+fn read_le16(data: &[u8], pos: usize) -> Result<u16, ArchiveError> {
+    match data.get(pos..offset(pos, 2)?) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(ArchiveError::Corrupted),
+    }
+}
+
+fn read_le32(data: &[u8], pos: usize) -> Result<u32, ArchiveError> {
+    match data.get(pos..offset(pos, 4)?) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(ArchiveError::Corrupted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = vec![];
+        let mut central = vec![];
+        for (name, data) in files {
+            let offset = zip.len() as u32;
+            let mut fields = vec![];
+            fields.extend_from_slice(&[0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+            fields.extend_from_slice(&crc32(data).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0x00, 0x00]);
+
+            zip.extend_from_slice(&ZIP_LOCAL_HEADER.to_le_bytes());
+            zip.extend_from_slice(&fields);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(data);
+
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&[0x14, 0x00]);
+            central.extend_from_slice(&fields);
+            central.extend_from_slice(&[0x00; 10]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+
+        let offset = zip.len() as u32;
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        zip.extend_from_slice(&[0x00; 4]);
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&offset.to_le_bytes());
+        zip.extend_from_slice(&[0x00, 0x00]);
+        zip
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let zip = build_zip(&[("readme.txt", b"hello"), ("game.GB", b"rom"), ("other.gbc", b"rom2")]);
+        assert_eq!(Ok(b"rom".to_vec()), extract_rom(zip.clone(), None));
+        assert_eq!(Ok(b"rom2".to_vec()), extract_rom(zip.clone(), Some("other.gbc")));
+        assert_eq!(
+            Err(ArchiveError::EntryNotFound("missing.gb".to_owned())),
+            extract_rom(zip, Some("missing.gb"))
+        );
+
+        let zip = build_zip(&[("readme.txt", b"hello")]);
+        assert_eq!(Err(ArchiveError::NoRom), extract_rom(zip, None));
+    }
+
+    #[test]
+    fn test_extract_gzip() {
+        // "hello hello hello" compressed by gzip, with the original file name
+        let mut gzip = vec![0x1F, 0x8B, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        gzip.extend_from_slice(b"a.gb\0");
+        gzip.extend_from_slice(&[0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x00]);
+        gzip.extend_from_slice(&crc32(b"hello hello hello").to_le_bytes());
+        gzip.extend_from_slice(&17u32.to_le_bytes());
+        assert_eq!(Ok(b"hello hello hello".to_vec()), extract_rom(gzip.clone(), None));
+
+        let trailer = gzip.len() - 8;
+        gzip[trailer] ^= 0xFF;
+        assert_eq!(Err(ArchiveError::ChecksumMismatch), extract_rom(gzip, None));
+    }
+
+    #[test]
+    fn test_extract_bomb() {
+        // A fixed Huffman block of one literal followed by copies of length 258 at distance 1
+        let mut bits = vec![1, 1, 0];
+        let mut code = |value: u32, len: u32| bits.extend((0..len).rev().map(|i| (value >> i) & 1));
+        code(0x30 + b'a' as u32, 8);
+        for _ in 0..MAX_ROM_SIZE / 258 + 1 {
+            code(0xC5, 8);
+            code(0, 5);
+        }
+        code(0, 7);
+        let mut deflate = vec![0; bits.len().div_ceil(8)];
+        for (i, bit) in bits.iter().enumerate() {
+            deflate[i / 8] |= (*bit as u8) << (i % 8);
+        }
+
+        let mut gzip = vec![0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03];
+        gzip.extend_from_slice(&deflate);
+        gzip.extend_from_slice(&[0x00; 8]);
+        assert_eq!(Err(ArchiveError::TooLarge), extract_rom(gzip, None));
+    }
+
+    #[test]
+    fn test_extract_zip_bad_offsets() {
+        let zip = build_zip(&[("game.gb", b"rom")]);
+        let central = read_le32(&zip, zip.len() - 6).unwrap() as usize;
+
+        let mut bad_size = zip.clone();
+        bad_size[central + 20..central + 24].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Err(ArchiveError::Corrupted), extract_rom(bad_size, None));
+
+        let mut bad_header = zip.clone();
+        bad_header[central + 42..central + 46].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Err(ArchiveError::Corrupted), extract_rom(bad_header, None));
+
+        let mut bad_directory = zip;
+        let end = bad_directory.len() - 6;
+        bad_directory[end..end + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Err(ArchiveError::Corrupted), extract_rom(bad_directory, None));
+    }
+
+    #[test]
+    fn test_extract_plain_rom() {
+        assert_eq!(Ok(vec![0x00; 0x150]), extract_rom(vec![0x00; 0x150], None));
+    }
+}
@@ -0,0 +1,282 @@
+use super::image_source::{ImageSource, TestPattern, IMAGE_HEIGHT, IMAGE_WIDTH};
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+// M64282FP registers: control, N/VH/gain, exposure (2), edge/invert/voltage, offset,
+// followed by a 4x4 matrix of 3 thresholds used for dithering
+const REGISTER_COUNT: usize = 0x36;
+const DITHER_MATRIX: usize = 0x06;
+
+// The captured picture is stored as 16x14 tiles right after the first 256 bytes of RAM
+const IMAGE_ADDR: usize = 0x0100;
+
+// The camera always has 128 KiB of RAM, whatever the header says, since captures are stored in it
+const RAM_SIZE: usize = 0x20000;
+
+// Exposure at which the sensor output matches the source image
+const EXPOSURE_UNITY: f32 = 0x1000 as f32;
+
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+    ram_enabled: bool,
+
+    // Bit 4 of the RAM bank register maps the sensor registers to 0xA000..=0xBFFF
+    registers_mapped: bool,
+    registers: [u8; REGISTER_COUNT],
+    capture_cycles: u32,
+
+    source: Box<dyn ImageSource>,
+
+    dirty: bool,
+}
+
+impl PocketCamera {
+    pub fn new(data: Vec<u8>) -> Self {
+        PocketCamera {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; RAM_SIZE],
+            ram_bank: 0,
+            ram_enabled: false,
+
+            registers_mapped: false,
+            registers: [0x00; REGISTER_COUNT],
+            capture_cycles: 0,
+
+            source: Box::new(TestPattern),
+
+            dirty: false,
+        }
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.registers[0] & 0x01 != 0
+    }
+
+    fn write_register(&mut self, addr: usize, data: u8) {
+        let index = addr & 0x7F;
+        match index {
+            0x00 => {
+                // The busy bit can only be set by the game and is cleared once the capture is done
+                let busy = self.registers[0] & 0x01;
+                self.registers[0] = busy | (data & 0x07);
+                if busy == 0 && data & 0x01 != 0 {
+                    self.capture_cycles = self.capture_time();
+                }
+            }
+            0x01..=0x35 => self.registers[index] = data,
+            _ => (),
+        };
+    }
+
+    // In CPU cycles, mostly spent reading the sensor out
+    fn capture_time(&self) -> u32 {
+        let n = self.registers[1] & 0x80 != 0;
+        let exposure = (self.registers[2] as u32) << 8 | self.registers[3] as u32;
+        129_784 + if n { 0 } else { 2048 } + exposure * 64
+    }
+
+    fn capture(&mut self) {
+        let pixels = self.source.capture();
+
+        let gain = 10f32.powf((self.registers[1] & 0x1F) as f32 * 1.5 / 20.0);
+        let exposure = ((self.registers[2] as u32) << 8 | self.registers[3] as u32) as f32 / EXPOSURE_UNITY;
+        let sensor = |x: isize, y: isize| {
+            let x = x.clamp(0, IMAGE_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, IMAGE_HEIGHT as isize - 1) as usize;
+            pixels.get(y * IMAGE_WIDTH + x).copied().unwrap_or(0x00) as f32 * gain * exposure
+        };
+
+        // Edge enhancement in both directions is selected by N and VH all being set
+        let edge = self.registers[1] & 0xE0 == 0xE0;
+        let ratio = EDGE_RATIOS[(self.registers[4] >> 4) as usize & 0x07];
+        let invert = self.registers[4] & 0x08 != 0;
+
+        for y in 0..IMAGE_HEIGHT {
+            for x in 0..IMAGE_WIDTH {
+                let (sx, sy) = (x as isize, y as isize);
+                let mut value = sensor(sx, sy);
+                if edge {
+                    let around = sensor(sx - 1, sy) + sensor(sx + 1, sy) + sensor(sx, sy - 1) + sensor(sx, sy + 1);
+                    value += (value * 4.0 - around) * ratio;
+                }
+                let mut value = value.clamp(0.0, 255.0) as u8;
+                if invert {
+                    value = 0xFF - value;
+                }
+
+                let thresholds = DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+                let shade = match value {
+                    v if v < self.registers[thresholds] => 3,
+                    v if v < self.registers[thresholds + 1] => 2,
+                    v if v < self.registers[thresholds + 2] => 1,
+                    _ => 0,
+                };
+                self.plot(x, y, shade);
+            }
+        }
+    }
+
+    fn plot(&mut self, x: usize, y: usize, shade: u8) {
+        let tile = (y / 8) * (IMAGE_WIDTH / 8) + x / 8;
+        let addr = IMAGE_ADDR + tile * 16 + (y % 8) * 2;
+        let mask = 0x80 >> (x % 8);
+
+        for (i, byte) in self.ram[addr..addr + 2].iter_mut().enumerate() {
+            if shade & (1 << i) != 0 {
+                *byte |= mask;
+            } else {
+                *byte &= !mask;
+            }
+        }
+    }
+}
+
+impl MemoryBankController for PocketCamera {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xBFFF => {
+                // Only the control register can be read back
+                if self.registers_mapped {
+                    return if addr & 0x7F == 0 { self.registers[0] } else { 0x00 };
+                }
+                // RAM is readable even while disabled, but not while the sensor is writing to it
+                if self.is_capturing() {
+                    return 0x00;
+                }
+
+                read_ram_bank(&self.ram, self.ram_bank, addr)
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            // Unlike most MBCs, bank 0 can be mapped here as well
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x3F) as usize,
+            0x4000..=0x5FFF => {
+                self.registers_mapped = data & 0x10 != 0;
+                self.ram_bank = (data & 0x0F) as usize;
+            }
+            0x6000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if self.registers_mapped {
+                    self.write_register(addr, data);
+                    return;
+                }
+                if !self.ram_enabled || self.is_capturing() {
+                    return;
+                }
+
+                self.dirty |= write_ram_bank(&mut self.ram, self.ram_bank, addr, data);
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
+
+    fn step(&mut self, cycle: u8) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycle as u32);
+        if self.capture_cycles == 0 {
+            self.capture();
+            self.registers[0] &= !0x01;
+            self.dirty = true;
+        }
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::image_source::StaticImage;
+    use super::*;
+
+    fn capture(mbc: &mut PocketCamera) {
+        mbc.write(0x4000, 0x10);
+        mbc.write(0xA000, 0x01);
+        assert_eq!(0x01, mbc.read(0xA000));
+        while mbc.read(0xA000) & 0x01 != 0 {
+            mbc.step(0xFF);
+        }
+        mbc.write(0x4000, 0x00);
+    }
+
+    fn build_camera(pixels: Vec<u8>) -> PocketCamera {
+        let mut mbc = PocketCamera::new(vec![0x00; 0x8000]);
+        mbc.set_image_source(Box::new(StaticImage::new(pixels)));
+
+        mbc.write(0x4000, 0x10);
+        mbc.write(0xA002, 0x10); // Unity exposure
+        mbc.write(0xA003, 0x00);
+        for i in 0..16 {
+            mbc.write(0xA006 + i * 3, 0x40);
+            mbc.write(0xA007 + i * 3, 0x80);
+            mbc.write(0xA008 + i * 3, 0xC0);
+        }
+        mbc
+    }
+
+    #[test]
+    fn test_camera_registers() {
+        let mut mbc = build_camera(vec![]);
+        assert_eq!(0x00, mbc.read(0xA001));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x00);
+        mbc.write(0xA000, 0xAB);
+        assert_eq!(0xAB, mbc.read(0xA000));
+
+        // RAM is not accessible during a capture
+        mbc.write(0x4000, 0x10);
+        mbc.write(0xA000, 0x01);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(0x00, mbc.read(0xA000));
+
+        capture(&mut mbc);
+        assert_eq!(0xAB, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_camera_capture() {
+        // Left half black and right half light grey
+        let pixels = (0..IMAGE_WIDTH * IMAGE_HEIGHT)
+            .map(|i| if i % IMAGE_WIDTH < 64 { 0x00 } else { 0x90 })
+            .collect();
+        let mut mbc = build_camera(pixels);
+        capture(&mut mbc);
+
+        // First row of the first tile is black, and of the tile right of the middle light grey
+        assert_eq!(0xFF, mbc.read(0xA100));
+        assert_eq!(0xFF, mbc.read(0xA101));
+        assert_eq!(0xFF, mbc.read(0xA100 + 8 * 16));
+        assert_eq!(0x00, mbc.read(0xA101 + 8 * 16));
+    }
+}
@@ -0,0 +1,78 @@
+pub const CLOCK_HZ: u32 = 4_194_304;
+
+#[derive(Copy, Clone)]
+pub enum RtcMode {
+    // The clock advances with emulated CPU cycles
+    Emulated,
+
+    // The clock follows the host's wall clock (given as UNIX time in seconds)
+    Host(fn() -> u64),
+}
+
+// Time source shared by the cartridges with a clock on board
+pub struct Clock {
+    mode: RtcMode,
+    cycles: u32,
+    host_time: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Clock {
+            mode: RtcMode::Emulated,
+            cycles: 0,
+            host_time: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        self.mode = mode;
+        if let RtcMode::Host(now) = mode {
+            self.host_time = now();
+        }
+    }
+
+    // Returns how many seconds have passed in emulated time
+    pub fn step(&mut self, cycle: u8) -> u64 {
+        if let RtcMode::Host(_) = self.mode {
+            return 0;
+        }
+
+        self.cycles += cycle as u32;
+        let secs = self.cycles / CLOCK_HZ;
+        self.cycles %= CLOCK_HZ;
+        secs as u64
+    }
+
+    // Returns how many seconds have passed on the host since the last sync
+    pub fn sync(&mut self) -> u64 {
+        match self.mode {
+            RtcMode::Emulated => 0,
+            RtcMode::Host(now) => {
+                let now = now();
+                let elapsed = now.saturating_sub(self.host_time);
+                self.host_time = now;
+                elapsed
+            }
+        }
+    }
+
+    pub fn reset_cycles(&mut self) {
+        self.cycles = 0;
+    }
+
+    // UNIX time the clock was last synced at, or 0 when running on emulated time
+    pub fn timestamp(&self) -> u64 {
+        self.host_time
+    }
+
+    // Picks up from the time a save file was written at, so that the time the emulator
+    // was not running gets counted on the next sync
+    pub fn restore_timestamp(&mut self, timestamp: u64) {
+        if let RtcMode::Host(_) = self.mode {
+            if timestamp != 0 {
+                self.host_time = timestamp;
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use super::header::cartridge_type_name;
+use std::error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    // The data ends before the cartridge header does
+    TooSmall(usize),
+    UnsupportedType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CartridgeError::*;
+
+        match *self {
+            TooSmall(size) => write!(f, "ROM is too small to contain a header ({} bytes)", size),
+            UnsupportedType(cart_type) => write!(
+                f,
+                "unsupported cartridge type: {} (0x{:02X})",
+                cartridge_type_name(cart_type),
+                cart_type
+            ),
+            UnknownRomSize(code) => write!(f, "unknown ROM size in header: 0x{:02X}", code),
+            UnknownRamSize(code) => write!(f, "unknown RAM size in header: 0x{:02X}", code),
+            SizeMismatch { expected, actual } => write!(
+                f,
+                "ROM is smaller than its header states (expected {} bytes, got {} bytes)",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {}
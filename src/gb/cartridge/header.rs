@@ -0,0 +1,329 @@
+use std::fmt;
+
+pub const HEADER_END_ADDR: usize = 0x0150;
+pub const LOGO_ADDR: usize = 0x0104;
+
+// The boot ROM compares this bitmap against the cartridge before starting it
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11,
+    0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E,
+    0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const TITLE_ADDR: usize = 0x0134;
+const MANUFACTURER_CODE_ADDR: usize = 0x013F;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const NEW_LICENSEE_CODE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
+pub const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+pub const ROM_SIZE_ADDR: usize = 0x0148;
+pub const RAM_SIZE_ADDR: usize = 0x0149;
+const DESTINATION_ADDR: usize = 0x014A;
+const OLD_LICENSEE_CODE_ADDR: usize = 0x014B;
+const VERSION_ADDR: usize = 0x014C;
+const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x014E;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CgbSupport {
+    None,
+    Compatible,
+    Only,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Licensee {
+    Old(u8),
+    New(String),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Destination {
+    Japanese,
+    Overseas,
+}
+
+#[derive(Debug, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub licensee: Licensee,
+    pub cartridge_type: u8,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    pub destination: Destination,
+    pub version: u8,
+
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    header_checksum_valid: bool,
+    global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_END_ADDR {
+            return None;
+        }
+
+        let cgb_support = match data[CGB_FLAG_ADDR] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // Later cartridges carve the manufacturer code and CGB flag out of the title area
+        let manufacturer_code = &data[MANUFACTURER_CODE_ADDR..CGB_FLAG_ADDR];
+        let has_manufacturer_code = cgb_support != CgbSupport::None
+            && manufacturer_code
+                .iter()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        let (title_end, manufacturer_code) = match (has_manufacturer_code, cgb_support) {
+            (true, _) => (MANUFACTURER_CODE_ADDR, Some(decode_ascii(manufacturer_code))),
+            (false, CgbSupport::None) => (NEW_LICENSEE_CODE_ADDR, None),
+            (false, _) => (CGB_FLAG_ADDR, None),
+        };
+
+        let licensee = match data[OLD_LICENSEE_CODE_ADDR] {
+            0x33 => Licensee::New(decode_ascii(&data[NEW_LICENSEE_CODE_ADDR..SGB_FLAG_ADDR])),
+            code => Licensee::Old(code),
+        };
+
+        let header_checksum = data[HEADER_CHECKSUM_ADDR];
+        let global_checksum = (data[GLOBAL_CHECKSUM_ADDR] as u16) << 8 | data[GLOBAL_CHECKSUM_ADDR + 1] as u16;
+
+        Some(CartridgeHeader {
+            title: decode_ascii(&data[TITLE_ADDR..title_end]),
+            manufacturer_code,
+            cgb_support,
+            sgb_support: data[SGB_FLAG_ADDR] == 0x03,
+            licensee,
+            cartridge_type: data[CARTRIDGE_TYPE_ADDR],
+            rom_size_code: data[ROM_SIZE_ADDR],
+            ram_size_code: data[RAM_SIZE_ADDR],
+            destination: if data[DESTINATION_ADDR] == 0x00 {
+                Destination::Japanese
+            } else {
+                Destination::Overseas
+            },
+            version: data[VERSION_ADDR],
+
+            header_checksum,
+            global_checksum,
+            header_checksum_valid: compute_header_checksum(data) == header_checksum,
+            global_checksum_valid: compute_global_checksum(data) == global_checksum,
+        })
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        cartridge_type_name(self.cartridge_type)
+    }
+
+    // ROM size in bytes, or None for an unknown size code
+    pub fn rom_size(&self) -> Option<usize> {
+        match self.rom_size_code {
+            0x00..=0x08 => Some(0x8000 << self.rom_size_code),
+            0x52 => Some(72 * 0x4000),
+            0x53 => Some(80 * 0x4000),
+            0x54 => Some(96 * 0x4000),
+            _ => None,
+        }
+    }
+
+    // External RAM size in bytes, or None for an unknown size code
+    pub fn ram_size(&self) -> Option<usize> {
+        match self.ram_size_code {
+            0x00 => Some(0),
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        }
+    }
+
+    // The boot ROM refuses to start a cartridge whose header checksum does not match
+    pub fn is_header_checksum_valid(&self) -> bool {
+        self.header_checksum_valid
+    }
+
+    // Nothing on real hardware verifies this one, so many ROM hacks leave it stale
+    pub fn is_global_checksum_valid(&self) -> bool {
+        self.global_checksum_valid
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let licensee = match &self.licensee {
+            Licensee::Old(code) => format!("0x{:02X}", code),
+            Licensee::New(code) => format!("\"{}\"", code),
+        };
+        let size = |size: Option<usize>| match size {
+            Some(size) => format!("{} KiB", size / 1024),
+            None => "unknown".to_owned(),
+        };
+
+        writeln!(f, "Title:           {}", self.title)?;
+        if let Some(code) = &self.manufacturer_code {
+            writeln!(f, "Manufacturer:    {}", code)?;
+        }
+        writeln!(f, "Licensee:        {}", licensee)?;
+        writeln!(
+            f,
+            "Cartridge type:  {} (0x{:02X})",
+            self.cartridge_type_name(),
+            self.cartridge_type
+        )?;
+        writeln!(f, "ROM size:        {}", size(self.rom_size()))?;
+        writeln!(f, "RAM size:        {}", size(self.ram_size()))?;
+        writeln!(f, "CGB support:     {:?}", self.cgb_support)?;
+        writeln!(f, "SGB support:     {}", self.sgb_support)?;
+        writeln!(f, "Destination:     {:?}", self.destination)?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(
+            f,
+            "Header checksum: 0x{:02X} ({})",
+            self.header_checksum,
+            if self.header_checksum_valid { "ok" } else { "mismatch" }
+        )?;
+        write!(
+            f,
+            "Global checksum: 0x{:04X} ({})",
+            self.global_checksum,
+            if self.global_checksum_valid { "ok" } else { "mismatch" }
+        )
+    }
+}
+
+pub fn cartridge_type_name(cart_type: u8) -> &'static str {
+    match cart_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "UNKNOWN",
+    }
+}
+
+fn decode_ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&c| c != 0x00)
+        .map(|&c| {
+            if c.is_ascii_graphic() || c == b' ' {
+                c as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+fn compute_header_checksum(data: &[u8]) -> u8 {
+    data[TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1))
+}
+
+fn compute_global_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM_ADDR && i != GLOBAL_CHECKSUM_ADDR + 1)
+        .fold(0u16, |x, (_, &b)| x.wrapping_add(b as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_rom(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[TITLE_ADDR..TITLE_ADDR + title.len()].copy_from_slice(title);
+        rom[CGB_FLAG_ADDR] = cgb_flag;
+        rom[OLD_LICENSEE_CODE_ADDR] = 0x33;
+        rom[NEW_LICENSEE_CODE_ADDR] = b'0';
+        rom[NEW_LICENSEE_CODE_ADDR + 1] = b'1';
+        rom[CARTRIDGE_TYPE_ADDR] = 0x13;
+        rom[ROM_SIZE_ADDR] = 0x05;
+        rom[RAM_SIZE_ADDR] = 0x03;
+        rom[DESTINATION_ADDR] = 0x01;
+
+        rom[HEADER_CHECKSUM_ADDR] = compute_header_checksum(&rom);
+        let checksum = compute_global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM_ADDR] = (checksum >> 8) as u8;
+        rom[GLOBAL_CHECKSUM_ADDR + 1] = checksum as u8;
+        rom
+    }
+
+    #[test]
+    fn test_header_parse() {
+        let rom = build_rom(b"POKEMON RED", 0x00);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert_eq!("POKEMON RED", header.title);
+        assert_eq!(None, header.manufacturer_code);
+        assert_eq!(CgbSupport::None, header.cgb_support);
+        assert_eq!(Licensee::New("01".to_owned()), header.licensee);
+        assert_eq!("MBC3+RAM+BATTERY", header.cartridge_type_name());
+        assert_eq!(Some(1024 * 1024), header.rom_size());
+        assert_eq!(Some(32 * 1024), header.ram_size());
+        assert_eq!(Destination::Overseas, header.destination);
+        assert!(header.is_header_checksum_valid());
+        assert!(header.is_global_checksum_valid());
+    }
+
+    #[test]
+    fn test_header_manufacturer_code() {
+        let rom = build_rom(b"POKEMON_GLDAAUE", 0x80);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert_eq!("POKEMON_GLD", header.title);
+        assert_eq!(Some("AAUE".to_owned()), header.manufacturer_code);
+        assert_eq!(CgbSupport::Compatible, header.cgb_support);
+    }
+
+    #[test]
+    fn test_header_checksum_mismatch() {
+        let mut rom = build_rom(b"TETRIS", 0x00);
+        rom[TITLE_ADDR] = b'X';
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        assert!(!header.is_header_checksum_valid());
+        assert!(!header.is_global_checksum_valid());
+    }
+
+    #[test]
+    fn test_header_too_short() {
+        assert!(CartridgeHeader::parse(&[0x00; 0x100]).is_none());
+    }
+}
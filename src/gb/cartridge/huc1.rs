@@ -0,0 +1,105 @@
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+pub struct Huc1 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+
+    // 0xA000..=0xBFFF is connected to the infrared port instead of RAM in IR mode
+    ir_mode: bool,
+    ir_led: bool,
+
+    dirty: bool,
+}
+
+impl Huc1 {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        Huc1 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+
+            ir_mode: false,
+            ir_led: false,
+
+            dirty: false,
+        }
+    }
+}
+
+impl MemoryBankController for Huc1 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xBFFF => {
+                if self.ir_mode {
+                    // Bit 0 would be set while receiving light, but there is never any peer
+                    return 0xC0;
+                }
+
+                read_ram_bank(&self.ram, self.ram_bank, addr)
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = data & 0x0F == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x3F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            0x6000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if self.ir_mode {
+                    self.ir_led = data & 0x01 != 0;
+                    return;
+                }
+
+                self.dirty |= write_ram_bank(&mut self.ram, self.ram_bank, addr, data);
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_huc1_ir_mode() {
+        let mut mbc = Huc1::new(vec![0x00; 0x8000], 0x2000);
+        mbc.write(0xA000, 0xAB);
+        assert_eq!(0xAB, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0E);
+        assert_eq!(0xC0, mbc.read(0xA000));
+        mbc.write(0xA000, 0x01);
+        assert!(mbc.ir_led);
+
+        mbc.write(0x0000, 0x00);
+        assert_eq!(0xAB, mbc.read(0xA000));
+    }
+}
@@ -0,0 +1,273 @@
+use super::clock::{Clock, RtcMode};
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+// Size of the RTC footer appended to .sav files: timestamp, minutes, days, alarm minutes,
+// alarm days and the alarm flag, all little-endian
+const HUC3_RTC_SAVE_SIZE: usize = 17;
+
+const MINUTES_PER_DAY: u16 = 60 * 24;
+
+pub struct Huc3 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+
+    // What 0xA000..=0xBFFF is connected to, selected by 0x0000..=0x1FFF
+    mode: u8,
+    ir_led: bool,
+
+    clock: Clock,
+    seconds: u64,
+    minutes: u16,
+    days: u16,
+    alarm_minutes: u16,
+    alarm_days: u16,
+    alarm_enabled: bool,
+
+    // The clock is accessed one nibble at a time through a command interface
+    access_index: u8,
+    response: u8,
+
+    dirty: bool,
+}
+
+impl Huc3 {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        Huc3 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+
+            mode: 0x00,
+            ir_led: false,
+
+            clock: Clock::new(),
+            seconds: 0,
+            minutes: 0,
+            days: 0,
+            alarm_minutes: 0,
+            alarm_days: 0,
+            alarm_enabled: false,
+
+            access_index: 0x00,
+            response: 0x00,
+
+            dirty: false,
+        }
+    }
+
+    fn execute(&mut self, command: u8) {
+        let secs = self.clock.sync();
+        self.advance(secs);
+
+        let arg = command & 0x0F;
+        match command >> 4 {
+            // Read a nibble and move on to the next one
+            0x1 => {
+                self.response = 0x10 | self.read_nibble(self.access_index);
+                self.access_index = self.access_index.wrapping_add(1);
+            }
+            // Write a nibble and move on to the next one
+            0x3 => {
+                self.write_nibble(self.access_index, arg);
+                self.access_index = self.access_index.wrapping_add(1);
+                self.response = command;
+            }
+            0x4 => {
+                self.access_index = (self.access_index & 0xF0) | arg;
+                self.response = command;
+            }
+            0x5 => {
+                self.access_index = (self.access_index & 0x0F) | arg << 4;
+                self.response = command;
+            }
+            _ => self.response = command,
+        };
+    }
+
+    fn read_nibble(&self, index: u8) -> u8 {
+        let nibble = |v: u16, i: u8| ((v >> (i * 4)) & 0x0F) as u8;
+
+        match index {
+            0x00..=0x02 => nibble(self.minutes, index),
+            0x03..=0x06 => nibble(self.days, index - 0x03),
+            0x58..=0x5A => nibble(self.alarm_minutes, index - 0x58),
+            0x5B..=0x5E => nibble(self.alarm_days, index - 0x5B),
+            0x5F => self.alarm_enabled as u8,
+            _ => 0x00,
+        }
+    }
+
+    fn write_nibble(&mut self, index: u8, v: u8) {
+        let nibble = |dst: &mut u16, i: u8| {
+            *dst &= !(0x0F << (i * 4));
+            *dst |= (v as u16) << (i * 4);
+        };
+
+        match index {
+            0x00..=0x02 => {
+                nibble(&mut self.minutes, index);
+                self.seconds = 0;
+                self.clock.reset_cycles();
+            }
+            0x03..=0x06 => nibble(&mut self.days, index - 0x03),
+            0x58..=0x5A => nibble(&mut self.alarm_minutes, index - 0x58),
+            0x5B..=0x5E => nibble(&mut self.alarm_days, index - 0x5B),
+            0x5F => self.alarm_enabled = v & 0x01 != 0,
+            _ => (),
+        };
+    }
+
+    fn advance(&mut self, secs: u64) {
+        self.seconds += secs;
+        if self.seconds < 60 {
+            return;
+        }
+
+        let minutes = self.minutes as u64 + self.seconds / 60;
+        self.seconds %= 60;
+        self.minutes = (minutes % MINUTES_PER_DAY as u64) as u16;
+        self.days = self.days.wrapping_add((minutes / MINUTES_PER_DAY as u64) as u16);
+    }
+}
+
+impl MemoryBankController for Huc3 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xBFFF => match self.mode {
+                0x00 | 0x0A => read_ram_bank(&self.ram, self.ram_bank, addr),
+                0x0C => self.response,
+                // Commands are executed immediately, so the semaphore always reads as ready
+                0x0D => 0x01,
+                // No light is ever received on the infrared port
+                0x0E => 0xC0,
+                _ => 0xFF,
+            },
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => self.mode = data & 0x0F,
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x7F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            0x6000..=0x7FFF => (),
+            0xA000..=0xBFFF => match self.mode {
+                0x0A => self.dirty |= write_ram_bank(&mut self.ram, self.ram_bank, addr, data),
+                0x0B => self.execute(data),
+                0x0E => self.ir_led = data & 0x01 != 0,
+                _ => (),
+            },
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.clock.timestamp().to_le_bytes());
+        data.extend_from_slice(&self.minutes.to_le_bytes());
+        data.extend_from_slice(&self.days.to_le_bytes());
+        data.extend_from_slice(&self.alarm_minutes.to_le_bytes());
+        data.extend_from_slice(&self.alarm_days.to_le_bytes());
+        data.push(self.alarm_enabled as u8);
+        data
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+
+        if data.len() != self.ram.len() + HUC3_RTC_SAVE_SIZE {
+            return;
+        }
+        let rtc = &data[self.ram.len()..];
+        let word = |i: usize| u16::from_le_bytes([rtc[i], rtc[i + 1]]);
+
+        let mut timestamp = [0x00; 8];
+        timestamp.copy_from_slice(&rtc[0..8]);
+        self.minutes = word(8) % MINUTES_PER_DAY;
+        self.days = word(10);
+        self.alarm_minutes = word(12);
+        self.alarm_days = word(14);
+        self.alarm_enabled = rtc[16] & 0x01 != 0;
+        self.seconds = 0;
+
+        self.clock.restore_timestamp(u64::from_le_bytes(timestamp));
+        let secs = self.clock.sync();
+        self.advance(secs);
+    }
+
+    fn step(&mut self, cycle: u8) {
+        let secs = self.clock.step(cycle);
+        self.advance(secs);
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.clock.set_mode(mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_clock(mbc: &mut Huc3) -> (u16, u16) {
+        let mut nibbles = vec![];
+        mbc.write(0x0000, 0x0B);
+        mbc.write(0xA000, 0x40);
+        mbc.write(0xA000, 0x50);
+        for _ in 0..7 {
+            mbc.write(0x0000, 0x0B);
+            mbc.write(0xA000, 0x10);
+            mbc.write(0x0000, 0x0C);
+            nibbles.push((mbc.read(0xA000) & 0x0F) as u16);
+        }
+
+        let minutes = nibbles[0] | nibbles[1] << 4 | nibbles[2] << 8;
+        let days = nibbles[3] | nibbles[4] << 4 | nibbles[5] << 8 | nibbles[6] << 12;
+        (minutes, days)
+    }
+
+    #[test]
+    fn test_huc3_clock() {
+        let mut mbc = Huc3::new(vec![0x00; 0x8000], 0x8000);
+
+        // Set the clock to 23:59 on day 0x0123
+        mbc.write(0x0000, 0x0B);
+        for command in &[0x40, 0x50, 0x3F, 0x39, 0x35, 0x33, 0x32, 0x31, 0x30] {
+            mbc.write(0xA000, *command);
+        }
+        assert_eq!((1439, 0x0123), read_clock(&mut mbc));
+
+        mbc.advance(60);
+        assert_eq!((0, 0x0124), read_clock(&mut mbc));
+    }
+
+    #[test]
+    fn test_huc3_save() {
+        let mut mbc = Huc3::new(vec![0x00; 0x8000], 0x2000);
+        mbc.advance(3600 * 50);
+
+        let data = mbc.dump_ram();
+        assert_eq!(0x2000 + HUC3_RTC_SAVE_SIZE, data.len());
+
+        let mut restored = Huc3::new(vec![0x00; 0x8000], 0x2000);
+        restored.restore_ram(&data);
+        assert_eq!((120, 2), read_clock(&mut restored));
+    }
+}
@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Part of the sensor the Pocket Camera actually exposes to the game
+pub const IMAGE_WIDTH: usize = 128;
+pub const IMAGE_HEIGHT: usize = 112;
+
+// What the camera sensor sees, as 8-bit luminance where 0x00 is black
+pub trait ImageSource {
+    // Returns IMAGE_WIDTH * IMAGE_HEIGHT pixels row by row
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+// The same picture on every capture
+pub struct StaticImage {
+    pixels: Vec<u8>,
+}
+
+impl StaticImage {
+    // Missing pixels are black and extra ones are ignored
+    pub fn new(mut pixels: Vec<u8>) -> Self {
+        pixels.resize(IMAGE_WIDTH * IMAGE_HEIGHT, 0x00);
+        StaticImage { pixels }
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+// Diagonal gradient from black in the top left to white in the bottom right
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let max = IMAGE_WIDTH + IMAGE_HEIGHT - 2;
+        (0..IMAGE_HEIGHT)
+            .flat_map(|y| (0..IMAGE_WIDTH).map(move |x| ((x + y) * 0xFF / max) as u8))
+            .collect()
+    }
+}
+
+// Latest frame pushed by the frontend, e.g. from a webcam. Clones share the same frame,
+// so one can be handed to the cartridge while the frontend keeps another to push into.
+#[derive(Clone)]
+pub struct SharedFrame {
+    frame: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SharedFrame {
+    pub fn new() -> Self {
+        SharedFrame {
+            frame: Rc::new(RefCell::new(vec![0x00; IMAGE_WIDTH * IMAGE_HEIGHT])),
+        }
+    }
+
+    pub fn push(&self, pixels: &[u8]) {
+        let mut frame = self.frame.borrow_mut();
+        let n = frame.len().min(pixels.len());
+        frame[..n].copy_from_slice(&pixels[..n]);
+    }
+}
+
+impl ImageSource for SharedFrame {
+    fn capture(&mut self) -> Vec<u8> {
+        self.frame.borrow().clone()
+    }
+}
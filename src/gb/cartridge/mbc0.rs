@@ -1,15 +1,19 @@
-use super::MemoryBankController;
+use super::{read_ram_bank, restore_ram, write_ram_bank, MemoryBankController};
 
 pub struct Mbc0 {
     rom: Vec<u8>,
     ram: Vec<u8>,
+
+    dirty: bool,
 }
 
 impl Mbc0 {
-    pub fn new(data: Vec<u8>) -> Self {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
         Mbc0 {
             rom: data,
-            ram: vec![0x00; 0x2000],
+            ram: vec![0x00; ram_size],
+
+            dirty: false,
         }
     }
 }
@@ -19,7 +23,7 @@ impl MemoryBankController for Mbc0 {
         let addr = addr as usize;
         match addr {
             0x0000..=0x7FFF => self.rom[addr],
-            0xA000..=0xBFFF => self.ram[addr - 0xA000],
+            0xA000..=0xBFFF => read_ram_bank(&self.ram, 0, addr),
             _ => panic!("inaccessible address"),
         }
     }
@@ -27,8 +31,20 @@ impl MemoryBankController for Mbc0 {
     fn write(&mut self, addr: u16, data: u8) {
         let addr = addr as usize;
         match addr {
-            0xA000..=0xBFFF => self.ram[addr - 0xA000] = data,
+            0xA000..=0xBFFF => self.dirty |= write_ram_bank(&mut self.ram, 0, addr, data),
             _ => { /* TODO: Consider if this case should be error */ }
         };
     }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
 }
@@ -1,4 +1,9 @@
-use super::MemoryBankController;
+use super::header::{LOGO_ADDR, NINTENDO_LOGO};
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+// MBC1M multicarts only ever come as 8 Mbit ROMs, with one game per 256 KiB
+const MULTICART_ROM_SIZE: usize = 0x100000;
+const MULTICART_GAME_SIZE: usize = 0x40000;
 
 enum MemoryModel {
     Model0,
@@ -7,24 +12,67 @@ enum MemoryModel {
 
 pub struct Mbc1 {
     rom: Vec<u8>,
-    rom_bank: usize,
     ram: Vec<u8>,
-    ram_bank: usize,
+
+    // 5-bit register at 0x2000..=0x3FFF and 2-bit register at 0x4000..=0x5FFF
+    bank1: u8,
+    bank2: u8,
 
     memory_model: MemoryModel,
     ram_enabled: bool,
+
+    // Multicarts leave bit 4 of the lower bank register unconnected
+    multicart: bool,
+
+    dirty: bool,
 }
 
 impl Mbc1 {
-    pub fn new(data: Vec<u8>) -> Self {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = is_multicart(&data);
+
         Mbc1 {
             rom: data,
-            rom_bank: 1,
-            ram: vec![0x00; 0x8000],
-            ram_bank: 0,
+            ram: vec![0x00; ram_size],
+
+            bank1: 1,
+            bank2: 0,
 
             memory_model: MemoryModel::Model0,
             ram_enabled: false,
+
+            multicart,
+
+            dirty: false,
+        }
+    }
+
+    fn bank2_shift(&self) -> usize {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    // In mode 1 the upper bits also switch 0x0000..=0x3FFF, which only matters for ROMs of
+    // 1 MiB and larger (smaller ones simply wrap back to bank 0)
+    fn rom_bank0(&self) -> usize {
+        match self.memory_model {
+            MemoryModel::Model0 => 0,
+            MemoryModel::Model1 => (self.bank2 as usize) << self.bank2_shift(),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        (self.bank2 as usize) << self.bank2_shift() | bank1 as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        match self.memory_model {
+            MemoryModel::Model0 => 0,
+            MemoryModel::Model1 => self.bank2 as usize,
         }
     }
 }
@@ -33,14 +81,14 @@ impl MemoryBankController for Mbc1 {
     fn read(&self, addr: u16) -> u8 {
         let addr = addr as usize;
         match addr {
-            0x0000..=0x3FFF => self.rom[addr],
-            0x4000..=0x7FFF => self.rom[(addr - 0x4000) + (self.rom_bank * 0x4000)],
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, self.rom_bank0(), addr),
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank(), addr),
             0xA000..=0xBFFF => {
                 if !self.ram_enabled {
                     return 0xFF;
                 }
 
-                self.ram[(addr - 0xA000) + (self.ram_bank * 0x2000)]
+                read_ram_bank(&self.ram, self.ram_bank(), addr)
             }
             _ => panic!("inaccessible address"),
         }
@@ -55,16 +103,13 @@ impl MemoryBankController for Mbc1 {
                 _ => (),
             },
             0x2000..=0x3FFF => {
-                self.rom_bank = (data & 0x1F) as usize | (self.rom_bank & 0xE0);
-                self.rom_bank = increment_rom_bank(self.rom_bank);
-            }
-            0x4000..=0x5FFF => match self.memory_model {
-                MemoryModel::Model0 => {
-                    self.rom_bank = ((data & 0x03) << 5) as usize | (self.rom_bank & 0x1F);
-                    self.rom_bank = increment_rom_bank(self.rom_bank);
+                // The zero check sees all 5 bits, even on multicarts
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
                 }
-                MemoryModel::Model1 => self.ram_bank = (data & 0x03) as usize,
-            },
+            }
+            0x4000..=0x5FFF => self.bank2 = data & 0x03,
             0x6000..=0x7FFF => match data & 0x01 {
                 0x00 => self.memory_model = MemoryModel::Model0,
                 0x01 => self.memory_model = MemoryModel::Model1,
@@ -75,16 +120,114 @@ impl MemoryBankController for Mbc1 {
                     return;
                 }
 
-                self.ram[(addr - 0xA000) + (self.ram_bank * 0x2000)] = data;
+                let bank = self.ram_bank();
+                self.dirty |= write_ram_bank(&mut self.ram, bank, addr, data);
             }
             _ => panic!("inaccessible address"),
         };
     }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
+}
+
+// There is no header flag for multicarts, but each of their games comes with its own
+// header, so the Nintendo logo shows up again at the 256 KiB boundaries
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MULTICART_ROM_SIZE {
+        return false;
+    }
+
+    let logos = (0..MULTICART_ROM_SIZE / MULTICART_GAME_SIZE)
+        .map(|i| i * MULTICART_GAME_SIZE + LOGO_ADDR)
+        .filter(|&addr| rom[addr..addr + NINTENDO_LOGO.len()] == NINTENDO_LOGO)
+        .count();
+    logos >= 2
 }
 
-fn increment_rom_bank(rom_bank: usize) -> usize {
-    match rom_bank {
-        0x00 | 0x20 | 0x40 | 0x60 => rom_bank + 1,
-        _ => rom_bank,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_rom(size: usize) -> Vec<u8> {
+        let mut rom = vec![0x00; size];
+        for bank in 0..size / 0x4000 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_mbc1_bank_zero() {
+        let mut mbc = Mbc1::new(build_rom(0x80000), 0);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(0x01, mbc.read(0x4000));
+        mbc.write(0x2000, 0x20); // Only the lower 5 bits are checked against zero
+        assert_eq!(0x01, mbc.read(0x4000));
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(0x1F, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_mbc1_large_rom() {
+        let mut mbc = Mbc1::new(build_rom(0x200000), 0);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0x2000, 0x03);
+        assert_eq!(0x43, mbc.read(0x4000));
+        assert_eq!(0x00, mbc.read(0x0000));
+
+        mbc.write(0x6000, 0x01);
+        assert_eq!(0x40, mbc.read(0x0000));
+        assert_eq!(0x43, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_mbc1_ram_banking() {
+        let mut mbc = Mbc1::new(build_rom(0x8000), 0x8000);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xA000, 0xAB); // Mode 0 always maps RAM bank 0
+        mbc.write(0x6000, 0x01);
+        assert_eq!(0x00, mbc.read(0xA000));
+        mbc.write(0xA000, 0xCD);
+
+        mbc.write(0x6000, 0x00);
+        assert_eq!(0xAB, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_mbc1_multicart() {
+        let mut rom = build_rom(MULTICART_ROM_SIZE);
+        for game in 0..4 {
+            let addr = game * MULTICART_GAME_SIZE + LOGO_ADDR;
+            rom[addr..addr + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+
+        let mut mbc = Mbc1::new(rom, 0);
+        assert!(mbc.multicart);
+
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x12); // Bit 4 is not connected
+        assert_eq!(0x12, mbc.read(0x4000));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(0x10, mbc.read(0x0000));
+    }
+
+    #[test]
+    fn test_mbc1_not_multicart() {
+        let mut rom = build_rom(MULTICART_ROM_SIZE);
+        rom[LOGO_ADDR..LOGO_ADDR + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+
+        let mbc = Mbc1::new(rom, 0);
+        assert!(!mbc.multicart);
     }
 }
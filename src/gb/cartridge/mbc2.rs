@@ -0,0 +1,117 @@
+use super::{read_rom_bank, MemoryBankController};
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+
+    // 512 x 4-bit RAM built into the MBC chip itself
+    ram: Vec<u8>,
+    ram_enabled: bool,
+
+    dirty: bool,
+}
+
+impl Mbc2 {
+    pub fn new(data: Vec<u8>) -> Self {
+        Mbc2 {
+            rom: data,
+            rom_bank: 1,
+
+            ram: vec![0x00; 0x200],
+            ram_enabled: false,
+
+            dirty: false,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc2 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                // Only the lower nibble is wired, the upper one reads as open bus
+                self.ram[addr & 0x1FF] | 0xF0
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            // Bit 8 of the address selects which register gets written
+            0x0000..=0x3FFF => {
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = data & 0x0F == 0x0A;
+                } else {
+                    self.rom_bank = (data & 0x0F) as usize;
+                    if self.rom_bank == 0 {
+                        self.rom_bank = 1;
+                    }
+                }
+            }
+            0x4000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                let addr = addr & 0x1FF;
+                self.dirty |= self.ram[addr] != data & 0x0F;
+                self.ram[addr] = data & 0x0F;
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        for (dst, src) in self.ram.iter_mut().zip(data) {
+            *dst = src & 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc2_register_select() {
+        let mut rom = vec![0x00; 0x4000 * 4];
+        rom[0x4000 * 2] = 0xAB;
+
+        let mut mbc = Mbc2::new(rom);
+        mbc.write(0x0000, 0x02); // Bit 8 is cleared, so this is not a bank switch
+        assert_eq!(0x00, mbc.read(0x4000));
+        mbc.write(0x0100, 0x02);
+        assert_eq!(0xAB, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_mbc2_ram() {
+        let mut mbc = Mbc2::new(vec![0x00; 0x8000]);
+        mbc.write(0xA000, 0x05);
+        assert_eq!(0xFF, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0xA5);
+        assert_eq!(0xF5, mbc.read(0xA000));
+        assert_eq!(0xF5, mbc.read(0xA200));
+        assert_eq!(0xF5, mbc.read(0xBE00));
+    }
+}
@@ -0,0 +1,166 @@
+use super::clock::RtcMode;
+use super::rtc::{Rtc, RTC_SAVE_SIZE};
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+    rtc: Option<Rtc>,
+
+    // RAM bank or RTC register selected by 0x4000..=0x5FFF
+    selected: u8,
+    ram_enabled: bool,
+
+    dirty: bool,
+}
+
+impl Mbc3 {
+    pub fn new(data: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self {
+        Mbc3 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+
+            selected: 0x00,
+            ram_enabled: false,
+
+            dirty: false,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc3 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                match (self.selected, &self.rtc) {
+                    (0x00..=0x03, _) => read_ram_bank(&self.ram, self.ram_bank, addr),
+                    (0x08..=0x0C, Some(rtc)) => rtc.read(self.selected),
+                    _ => 0xFF,
+                }
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => match data & 0x0F {
+                0x00 => self.ram_enabled = false,
+                0x0A => self.ram_enabled = true,
+                _ => (),
+            },
+            0x2000..=0x3FFF => {
+                self.rom_bank = (data & 0x7F) as usize;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.selected = data;
+                if data <= 0x03 {
+                    self.ram_bank = data as usize;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(data);
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                match (self.selected, &mut self.rtc) {
+                    (0x00..=0x03, _) => self.dirty |= write_ram_bank(&mut self.ram, self.ram_bank, addr, data),
+                    (0x08..=0x0C, Some(rtc)) => rtc.write(self.selected, data),
+                    _ => (),
+                };
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            data.extend(rtc.dump());
+        }
+        data
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+
+        if let Some(rtc) = &mut self.rtc {
+            if data.len() > self.ram.len() && data.len() <= self.ram.len() + RTC_SAVE_SIZE {
+                rtc.restore(&data[self.ram.len()..]);
+            }
+        }
+    }
+
+    fn step(&mut self, cycle: u8) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.step(cycle);
+        }
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.set_mode(mode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc3_banking() {
+        let mut rom = vec![0x00; 0x4000 * 4];
+        rom[0x4000 * 3] = 0xAB;
+
+        let mut mbc = Mbc3::new(rom, 0x8000, true);
+        mbc.write(0x2000, 0x03);
+        assert_eq!(0xAB, mbc.read(0x4000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xA000, 0xCD);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(0x00, mbc.read(0xA000));
+        mbc.write(0x4000, 0x02);
+        assert_eq!(0xCD, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_mbc3_rtc_registers() {
+        let mut mbc = Mbc3::new(vec![0x00; 0x8000], 0x8000, true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x09);
+        mbc.write(0xA000, 42);
+
+        assert_eq!(0x00, mbc.read(0xA000));
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(42, mbc.read(0xA000));
+    }
+}
@@ -1,23 +1,30 @@
-use super::MemoryBankController;
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
 
 pub struct Mbc5 {
     rom: Vec<u8>,
     rom_bank: usize,
     ram: Vec<u8>,
     ram_bank: usize,
+    // Bit 3 of the RAM bank number drives the motor instead
+    rumble: bool,
 
     ram_enabled: bool,
+
+    dirty: bool,
 }
 
 impl Mbc5 {
-    pub fn new(data: Vec<u8>) -> Self {
+    pub fn new(data: Vec<u8>, ram_size: usize, rumble: bool) -> Self {
         Mbc5 {
             rom: data,
             rom_bank: 1,
-            ram: vec![0x00; 0x20000],
+            ram: vec![0x00; ram_size],
             ram_bank: 0,
+            rumble,
 
             ram_enabled: false,
+
+            dirty: false,
         }
     }
 }
@@ -27,13 +34,13 @@ impl MemoryBankController for Mbc5 {
         let addr = addr as usize;
         match addr {
             0x0000..=0x3FFF => self.rom[addr],
-            0x4000..=0x7FFF => self.rom[(addr - 0x4000) + (self.rom_bank * 0x4000)],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
             0xA000..=0xBFFF => {
                 if !self.ram_enabled {
                     return 0xFF;
                 }
 
-                self.ram[(addr - 0xA000) + (self.ram_bank * 0x2000)]
+                read_ram_bank(&self.ram, self.ram_bank, addr)
             }
             _ => panic!("inaccessible address"),
         }
@@ -50,8 +57,52 @@ impl MemoryBankController for Mbc5 {
             },
             0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data,
             0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | ((data & 0x01) << 8),
-            0x4000..=0x5FFF => self.ram_bank = (data & 0x0F) as usize,
+            0x4000..=0x5FFF => self.ram_bank = data & if self.rumble { 0x07 } else { 0x0F },
+            0x6000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                self.dirty |= write_ram_bank(&mut self.ram, self.ram_bank, addr, data as u8);
+            }
             _ => panic!("inaccessible address"),
         };
     }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mbc5_rumble() {
+        let mut mbc = Mbc5::new(vec![0x00; 0x8000], 0x20000, true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0xAB);
+
+        // Turning the motor on keeps the same RAM bank
+        mbc.write(0x4000, 0x09);
+        assert_eq!(0xAB, mbc.read(0xA000));
+
+        let mut mbc = Mbc5::new(vec![0x00; 0x8000], 0x20000, false);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0xAB);
+        mbc.write(0x4000, 0x09);
+        assert_eq!(0x00, mbc.read(0xA000));
+    }
 }
@@ -0,0 +1,275 @@
+use super::{restore_ram, MemoryBankController};
+
+// MBC6 switches ROM, flash and RAM in halves of the usual windows
+const HALF_ROM_BANK_SIZE: usize = 0x2000;
+const HALF_RAM_BANK_SIZE: usize = 0x1000;
+
+// Net de Get comes with a 1 MiB MX29F008 flash chip and 32 KiB of RAM
+const FLASH_SIZE: usize = 0x100000;
+const MBC6_RAM_SIZE: usize = 0x8000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+const FLASH_ID: [u8; 2] = [0xC2, 0x81];
+
+#[derive(PartialEq)]
+enum FlashState {
+    Ready,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    EraseReady,
+    EraseUnlocked1,
+    EraseUnlocked2,
+    Id,
+}
+
+pub struct Mbc6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+
+    // Both 8 KiB windows at 0x4000 and 0x6000 can show either ROM or flash
+    banks: [usize; 2],
+    flash_mapped: [bool; 2],
+    ram_banks: [usize; 2],
+
+    ram_enabled: bool,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+    // RAM and flash are both saved
+    dirty: bool,
+}
+
+impl Mbc6 {
+    pub fn new(data: Vec<u8>) -> Self {
+        Mbc6 {
+            rom: data,
+            ram: vec![0x00; MBC6_RAM_SIZE],
+            flash: vec![0xFF; FLASH_SIZE],
+
+            banks: [0, 0],
+            flash_mapped: [false, false],
+            ram_banks: [0, 0],
+
+            ram_enabled: false,
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Ready,
+            dirty: false,
+        }
+    }
+
+    fn read_window(&self, window: usize, addr: usize) -> u8 {
+        let offset = addr & (HALF_ROM_BANK_SIZE - 1);
+        if !self.flash_mapped[window] {
+            let bank = self.banks[window] % (self.rom.len() / HALF_ROM_BANK_SIZE);
+            return self.rom[bank * HALF_ROM_BANK_SIZE + offset];
+        }
+
+        if !self.flash_enabled {
+            return 0xFF;
+        }
+        if self.flash_state == FlashState::Id {
+            return FLASH_ID[offset & 0x01];
+        }
+        self.flash[self.flash_addr(window, addr)]
+    }
+
+    fn flash_addr(&self, window: usize, addr: usize) -> usize {
+        (self.banks[window] * HALF_ROM_BANK_SIZE + (addr & (HALF_ROM_BANK_SIZE - 1))) % FLASH_SIZE
+    }
+
+    // JEDEC command sequences, addressed relative to the flash chip
+    fn write_flash(&mut self, window: usize, addr: usize, data: u8) {
+        if !self.flash_enabled || !self.flash_mapped[window] {
+            return;
+        }
+
+        let flash_addr = self.flash_addr(window, addr);
+        let command_addr = flash_addr & 0x7FFF;
+        // The byte following a program command is always data, even if it looks like a reset
+        if data == 0xF0 && self.flash_state != FlashState::Program {
+            self.flash_state = FlashState::Ready;
+            return;
+        }
+
+        self.flash_state = match self.flash_state {
+            FlashState::Ready | FlashState::Id if command_addr == 0x5555 && data == 0xAA => FlashState::Unlocked1,
+            FlashState::Unlocked1 if command_addr == 0x2AAA && data == 0x55 => FlashState::Unlocked2,
+            FlashState::Unlocked2 if command_addr == 0x5555 => match data {
+                0x80 => FlashState::EraseReady,
+                0x90 => FlashState::Id,
+                0xA0 => FlashState::Program,
+                _ => FlashState::Ready,
+            },
+            FlashState::Program => {
+                // Programming can only clear bits
+                if self.flash_write_enabled {
+                    self.flash[flash_addr] &= data;
+                    self.dirty = true;
+                }
+                FlashState::Ready
+            }
+            FlashState::EraseReady if command_addr == 0x5555 && data == 0xAA => FlashState::EraseUnlocked1,
+            FlashState::EraseUnlocked1 if command_addr == 0x2AAA && data == 0x55 => FlashState::EraseUnlocked2,
+            FlashState::EraseUnlocked2 => {
+                if self.flash_write_enabled {
+                    match data {
+                        0x10 if command_addr == 0x5555 => self.flash.iter_mut().for_each(|b| *b = 0xFF),
+                        0x30 => {
+                            let sector = flash_addr / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                            self.flash[sector..sector + FLASH_SECTOR_SIZE]
+                                .iter_mut()
+                                .for_each(|b| *b = 0xFF);
+                        }
+                        _ => (),
+                    };
+                    self.dirty = true;
+                }
+                FlashState::Ready
+            }
+            FlashState::Id => FlashState::Id,
+            _ => FlashState::Ready,
+        };
+    }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        let bank = self.ram_banks[(addr >> 12) & 0x01];
+        (bank * HALF_RAM_BANK_SIZE + (addr & (HALF_RAM_BANK_SIZE - 1))) % MBC6_RAM_SIZE
+    }
+}
+
+impl MemoryBankController for Mbc6 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x5FFF => self.read_window(0, addr),
+            0x6000..=0x7FFF => self.read_window(1, addr),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                self.ram[self.ram_addr(addr)]
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x03FF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = (data & 0x07) as usize,
+            0x0800..=0x0BFF => self.ram_banks[1] = (data & 0x07) as usize,
+            0x0C00..=0x0FFF => self.flash_enabled = data & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = data & 0x01 != 0,
+            0x1001..=0x1FFF => (),
+            0x2000..=0x27FF => self.banks[0] = (data & 0x7F) as usize,
+            0x2800..=0x2FFF => self.flash_mapped[0] = data == 0x08,
+            0x3000..=0x37FF => self.banks[1] = (data & 0x7F) as usize,
+            0x3800..=0x3FFF => self.flash_mapped[1] = data == 0x08,
+            0x4000..=0x5FFF => self.write_flash(0, addr, data),
+            0x6000..=0x7FFF => self.write_flash(1, addr, data),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                let addr = self.ram_addr(addr);
+                self.dirty |= self.ram[addr] != data;
+                self.ram[addr] = data;
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    // The flash keeps its contents without a battery, so it is saved after the RAM
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.flash);
+        data
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+        if data.len() > MBC6_RAM_SIZE {
+            restore_ram(&mut self.flash, &data[MBC6_RAM_SIZE..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash_command(mbc: &mut Mbc6, data: u8) {
+        mbc.write(0x2000, 0x02);
+        mbc.write(0x5555, 0xAA);
+        mbc.write(0x2000, 0x01);
+        mbc.write(0x4AAA, 0x55);
+        mbc.write(0x2000, 0x02);
+        mbc.write(0x5555, data);
+    }
+
+    #[test]
+    fn test_mbc6_banking() {
+        let mut rom = vec![0x00; 0x2000 * 8];
+        for bank in 0..8 {
+            rom[bank * 0x2000] = bank as u8;
+        }
+
+        let mut mbc = Mbc6::new(rom);
+        mbc.write(0x2000, 0x05);
+        mbc.write(0x3000, 0x03);
+        assert_eq!(0x05, mbc.read(0x4000));
+        assert_eq!(0x03, mbc.read(0x6000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x0400, 0x01);
+        mbc.write(0x0800, 0x02);
+        mbc.write(0xA000, 0xAB);
+        mbc.write(0xB000, 0xCD);
+        assert_eq!(0xAB, mbc.ram[0x1000]);
+        assert_eq!(0xCD, mbc.ram[0x2000]);
+    }
+
+    #[test]
+    fn test_mbc6_flash() {
+        let mut mbc = Mbc6::new(vec![0x00; 0x8000]);
+        mbc.write(0x0C00, 0x01);
+        mbc.write(0x1000, 0x01);
+        mbc.write(0x2800, 0x08);
+
+        flash_command(&mut mbc, 0x90);
+        assert_eq!(0xC2, mbc.read(0x4000));
+        assert_eq!(0x81, mbc.read(0x4001));
+        mbc.write(0x4000, 0xF0);
+
+        flash_command(&mut mbc, 0xA0);
+        mbc.write(0x2000, 0x04);
+        mbc.write(0x4010, 0x5A);
+        assert_eq!(0x5A, mbc.read(0x4010));
+        assert_eq!(0x5A, mbc.dump_ram()[MBC6_RAM_SIZE + 0x8010]);
+
+        assert!(mbc.take_dirty());
+
+        flash_command(&mut mbc, 0x80);
+        flash_command(&mut mbc, 0x10);
+        mbc.write(0x2000, 0x04);
+        assert_eq!(0xFF, mbc.read(0x4010));
+        assert!(mbc.take_dirty());
+        assert!(!mbc.take_dirty());
+
+        // 0xF0 is programmed as data rather than taken as a reset
+        flash_command(&mut mbc, 0xA0);
+        mbc.write(0x2000, 0x04);
+        mbc.write(0x4010, 0xF0);
+        assert_eq!(0xF0, mbc.read(0x4010));
+    }
+}
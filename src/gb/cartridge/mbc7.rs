@@ -0,0 +1,374 @@
+use super::{read_rom_bank, MemoryBankController};
+
+// Raw accelerometer reading when the cartridge is held level, and the offset for 1g
+const ACCEL_CENTER: u16 = 0x81D0;
+const ACCEL_1G: f32 = 112.0;
+
+// 93LC56 organised as 128 16-bit words
+const EEPROM_WORDS: usize = 128;
+
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+
+    // Both have to be set before the registers at 0xA000..=0xAFFF respond
+    ram_enabled1: bool,
+    ram_enabled2: bool,
+
+    tilt: (f32, f32),
+    accel_x: u16,
+    accel_y: u16,
+    accel_erased: bool,
+
+    eeprom: Eeprom,
+}
+
+impl Mbc7 {
+    pub fn new(data: Vec<u8>) -> Self {
+        Mbc7 {
+            rom: data,
+            rom_bank: 1,
+
+            ram_enabled1: false,
+            ram_enabled2: false,
+
+            tilt: (0.0, 0.0),
+            accel_x: 0x8000,
+            accel_y: 0x8000,
+            accel_erased: false,
+
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn read_register(&self, addr: usize) -> u8 {
+        match (addr >> 4) & 0x0F {
+            0x2 => self.accel_x as u8,
+            0x3 => (self.accel_x >> 8) as u8,
+            0x4 => self.accel_y as u8,
+            0x5 => (self.accel_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_register(&mut self, addr: usize, data: u8) {
+        match (addr >> 4) & 0x0F {
+            0x0 if data == 0x55 => {
+                self.accel_x = 0x8000;
+                self.accel_y = 0x8000;
+                self.accel_erased = true;
+            }
+            0x1 if data == 0xAA && self.accel_erased => {
+                // Tilting to the right lowers X, tilting towards the player raises Y
+                self.accel_x = (ACCEL_CENTER as f32 - self.tilt.0 * ACCEL_1G) as u16;
+                self.accel_y = (ACCEL_CENTER as f32 + self.tilt.1 * ACCEL_1G) as u16;
+                self.accel_erased = false;
+            }
+            0x8 => self.eeprom.write(data),
+            _ => (),
+        };
+    }
+}
+
+impl MemoryBankController for Mbc7 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank, addr),
+            0xA000..=0xAFFF => {
+                if !(self.ram_enabled1 && self.ram_enabled2) {
+                    return 0xFF;
+                }
+
+                self.read_register(addr)
+            }
+            0xB000..=0xBFFF => 0xFF,
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled1 = data == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (data & 0x7F) as usize,
+            0x4000..=0x5FFF => self.ram_enabled2 = data == 0x40,
+            0x6000..=0x7FFF => (),
+            0xA000..=0xAFFF => {
+                if !(self.ram_enabled1 && self.ram_enabled2) {
+                    return;
+                }
+
+                self.write_register(addr, data);
+            }
+            0xB000..=0xBFFF => (),
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.eeprom.dirty)
+    }
+
+    // The EEPROM is saved as little-endian words
+    fn dump_ram(&self) -> Vec<u8> {
+        self.eeprom.words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+    }
+}
+
+enum EepromState {
+    Idle,
+    Command { bits: u16, count: u8 },
+    Read { data: u16, count: u8 },
+    Write { addr: Option<usize>, data: u16, count: u8 },
+}
+
+// 93LC56 serial EEPROM, driven bit by bit through the Microwire interface
+struct Eeprom {
+    words: Vec<u16>,
+    dirty: bool,
+    state: EepromState,
+    write_enabled: bool,
+
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            words: vec![0xFFFF; EEPROM_WORDS],
+            dirty: false,
+            state: EepromState::Idle,
+            write_enabled: false,
+
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.dout as u8
+    }
+
+    fn write(&mut self, data: u8) {
+        let cs = data & 0x80 != 0;
+        let clk = data & 0x40 != 0;
+        let rising = clk && !self.clk;
+        self.cs = cs;
+        self.clk = clk;
+        self.di = data & 0x02 != 0;
+
+        if !cs {
+            self.state = EepromState::Idle;
+            self.dout = true;
+            return;
+        }
+        if rising {
+            self.clock_in(self.di);
+        }
+    }
+
+    fn clock_in(&mut self, di: bool) {
+        self.state = match self.state {
+            // Leading zeros are ignored until the start bit comes in
+            EepromState::Idle if di => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+
+            // 2-bit opcode followed by an 8-bit address
+            EepromState::Command { bits, count } => {
+                let bits = bits << 1 | di as u16;
+                if count + 1 < 10 {
+                    EepromState::Command { bits, count: count + 1 }
+                } else {
+                    self.decode(bits)
+                }
+            }
+
+            EepromState::Read { data, count } => {
+                self.dout = data & 0x8000 != 0;
+                if count + 1 < 16 {
+                    EepromState::Read {
+                        data: data << 1,
+                        count: count + 1,
+                    }
+                } else {
+                    EepromState::Idle
+                }
+            }
+
+            EepromState::Write { addr, data, count } => {
+                let data = data << 1 | di as u16;
+                if count + 1 < 16 {
+                    EepromState::Write {
+                        addr,
+                        data,
+                        count: count + 1,
+                    }
+                } else {
+                    if self.write_enabled {
+                        match addr {
+                            Some(addr) => self.words[addr] = data,
+                            None => self.words.iter_mut().for_each(|w| *w = data),
+                        };
+                        self.dirty = true;
+                    }
+                    self.dout = true;
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    fn decode(&mut self, bits: u16) -> EepromState {
+        let addr = (bits & 0xFF) as usize % EEPROM_WORDS;
+
+        match bits >> 8 {
+            // READ (a dummy zero precedes the data)
+            0b10 => {
+                self.dout = false;
+                EepromState::Read {
+                    data: self.words[addr],
+                    count: 0,
+                }
+            }
+            // WRITE
+            0b01 => EepromState::Write {
+                addr: Some(addr),
+                data: 0,
+                count: 0,
+            },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.words[addr] = 0xFFFF;
+                    self.dirty = true;
+                }
+                EepromState::Idle
+            }
+            _ => match (bits >> 6) & 0b11 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Idle
+                }
+                // WRAL
+                0b01 => EepromState::Write {
+                    addr: None,
+                    data: 0,
+                    count: 0,
+                },
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.words.iter_mut().for_each(|w| *w = 0xFFFF);
+                        self.dirty = true;
+                    }
+                    EepromState::Idle
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    EepromState::Idle
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enable(mbc: &mut Mbc7) {
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x40);
+    }
+
+    // Start bit, opcode and address
+    fn send_command(mbc: &mut Mbc7, opcode: u32, addr: u32) {
+        send_bits(mbc, 1 << 10 | opcode << 8 | addr, 11);
+    }
+
+    fn send_bits(mbc: &mut Mbc7, bits: u32, count: u8) {
+        for i in (0..count).rev() {
+            let di = (((bits >> i) & 1) as u8) << 1;
+            mbc.write(0xA080, 0x80 | di);
+            mbc.write(0xA080, 0xC0 | di);
+        }
+    }
+
+    fn receive_word(mbc: &mut Mbc7) -> u16 {
+        let mut word = 0;
+        for _ in 0..16 {
+            mbc.write(0xA080, 0x80);
+            mbc.write(0xA080, 0xC0);
+            word = word << 1 | (mbc.read(0xA080) & 0x01) as u16;
+        }
+        word
+    }
+
+    fn deselect(mbc: &mut Mbc7) {
+        mbc.write(0xA080, 0x00);
+    }
+
+    #[test]
+    fn test_mbc7_accelerometer() {
+        let mut mbc = Mbc7::new(vec![0x00; 0x8000]);
+        enable(&mut mbc);
+        mbc.set_tilt(0.0, 0.5);
+
+        mbc.write(0xA000, 0x55);
+        assert_eq!(0x00, mbc.read(0xA020));
+        assert_eq!(0x80, mbc.read(0xA030));
+
+        mbc.write(0xA010, 0xAA);
+        assert_eq!(ACCEL_CENTER, mbc.read(0xA020) as u16 | (mbc.read(0xA030) as u16) << 8);
+        assert_eq!(
+            ACCEL_CENTER + 56,
+            mbc.read(0xA040) as u16 | (mbc.read(0xA050) as u16) << 8
+        );
+    }
+
+    #[test]
+    fn test_mbc7_eeprom() {
+        let mut mbc = Mbc7::new(vec![0x00; 0x8000]);
+        enable(&mut mbc);
+
+        // Writes are ignored until EWEN
+        send_command(&mut mbc, 0b01, 5);
+        send_bits(&mut mbc, 0x1234, 16);
+        deselect(&mut mbc);
+        assert_eq!(0xFFFF, mbc.eeprom.words[5]);
+
+        send_command(&mut mbc, 0b00, 0xC0);
+        deselect(&mut mbc);
+        send_command(&mut mbc, 0b01, 5);
+        send_bits(&mut mbc, 0x1234, 16);
+        deselect(&mut mbc);
+
+        send_command(&mut mbc, 0b10, 5);
+        assert_eq!(0x00, mbc.read(0xA080) & 0x01); // Dummy bit
+        assert_eq!(0x1234, receive_word(&mut mbc));
+        deselect(&mut mbc);
+
+        assert_eq!(vec![0x34, 0x12], mbc.dump_ram()[10..12].to_vec());
+    }
+}
@@ -0,0 +1,186 @@
+use super::{read_ram_bank, read_rom_bank, restore_ram, write_ram_bank, MemoryBankController};
+
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    // Until a game is mapped, the menu in the last 32 KiB of the ROM is visible
+    // and the outer bank bits are still writable
+    mapped: bool,
+
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    // Bits 1-4 of the low ROM bank that are frozen once a game is mapped
+    rom_bank_mask: u8,
+
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    ram_enabled: bool,
+
+    mode: u8,
+    mode_locked: bool,
+
+    dirty: bool,
+}
+
+impl Mmm01 {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        Mmm01 {
+            rom: data,
+            ram: vec![0x00; ram_size],
+
+            mapped: false,
+
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_enabled: false,
+
+            mode: 0,
+            mode_locked: false,
+
+            dirty: false,
+        }
+    }
+
+    fn outer_bank(&self) -> usize {
+        (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5
+    }
+
+    fn rom_bank0(&self) -> usize {
+        if !self.mapped {
+            return 0x1FE;
+        }
+
+        self.outer_bank() | (self.rom_bank_low & self.rom_bank_mask) as usize
+    }
+
+    fn rom_bank(&self) -> usize {
+        if !self.mapped {
+            return 0x1FF;
+        }
+
+        // As on MBC1, only the bits the game controls are checked against zero
+        let mut low = self.rom_bank_low;
+        if low & !self.rom_bank_mask == 0 {
+            low |= 0x01;
+        }
+        self.outer_bank() | low as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        let low = if self.mode == 1 { self.ram_bank_low } else { 0 };
+        (self.ram_bank_high << 2 | low) as usize
+    }
+}
+
+impl MemoryBankController for Mmm01 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(&self.rom, self.rom_bank0(), addr),
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank(), addr),
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                read_ram_bank(&self.ram, self.ram_bank(), addr)
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = data & 0x0F == 0x0A;
+                if data & 0x40 != 0 {
+                    self.mapped = true;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if self.mapped {
+                    let mask = self.rom_bank_mask;
+                    self.rom_bank_low = (self.rom_bank_low & mask) | (data & 0x1F & !mask);
+                } else {
+                    self.rom_bank_low = data & 0x1F;
+                    self.rom_bank_mid = (data >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = data & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (data >> 2) & 0x03;
+                    self.rom_bank_high = (data >> 4) & 0x03;
+                    self.mode_locked = data & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mode_locked {
+                    self.mode = data & 0x01;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (data >> 1) & 0x1E;
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                let bank = self.ram_bank();
+                self.dirty |= write_ram_bank(&mut self.ram, bank, addr, data);
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmm01_mapping() {
+        let mut rom = vec![0x00; 0x4000 * 32];
+        for bank in 0..32 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+
+        // The menu comes up first
+        let mut mbc = Mmm01::new(rom, 0);
+        assert_eq!(30, mbc.read(0x0000));
+        assert_eq!(31, mbc.read(0x4000));
+
+        // Map the game starting at bank 8, with 8 banks of its own
+        mbc.write(0x2000, 0x08);
+        mbc.write(0x6000, 0x30);
+        mbc.write(0x0000, 0x40);
+        assert_eq!(8, mbc.read(0x0000));
+        assert_eq!(9, mbc.read(0x4000));
+
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(15, mbc.read(0x4000));
+        mbc.write(0x2000, 0x00);
+        assert_eq!(9, mbc.read(0x4000));
+    }
+}
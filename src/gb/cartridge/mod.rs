@@ -1,45 +1,108 @@
+pub mod header;
+pub mod image_source;
+
+mod camera;
+mod clock;
+mod error;
+mod huc1;
+mod huc3;
 mod mbc0;
 mod mbc1;
+mod mbc2;
+mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rtc;
+mod tama5;
 
+use self::camera::PocketCamera;
+use self::huc1::Huc1;
+use self::huc3::Huc3;
 use self::mbc0::Mbc0;
 use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
 use self::mbc5::Mbc5;
+use self::mbc6::Mbc6;
+use self::mbc7::Mbc7;
+use self::mmm01::Mmm01;
+use self::tama5::Tama5;
+
+pub use self::clock::RtcMode;
+pub use self::error::CartridgeError;
+pub use self::header::CartridgeHeader;
+pub use self::image_source::ImageSource;
 
-const CARTRIDGE_TYPE_ADDR: u16 = 0x0147;
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
 
 pub struct Cartridge {
     mbc: Box<dyn MemoryBankController>,
+    header: CartridgeHeader,
+
+    has_battery: bool,
+    ram_dirty: bool,
 }
 
 impl Cartridge {
-    pub fn new(data: Vec<u8>) -> Self {
-        if data.len() < CARTRIDGE_TYPE_ADDR as usize {
-            // TODO: Should be treated as ROM probably
-            panic!("broken cartridge");
-        }
+    pub fn new(mut data: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = match CartridgeHeader::parse(&data) {
+            Some(header) => mmm01_menu_header(&data).unwrap_or(header),
+            None => return Err(CartridgeError::TooSmall(data.len())),
+        };
 
-        match data[CARTRIDGE_TYPE_ADDR as usize] {
-            0x00 | 0x08 | 0x09 => Cartridge {
-                mbc: Box::new(Mbc0::new(data)),
-            },
-            0x01 | 0x02 | 0x03 => Cartridge {
-                mbc: Box::new(Mbc1::new(data)),
-            },
-            0x05 | 0x06 => {
-                panic!("unsupported cartridge type: MBC2");
-            }
-            0x0F | 0x10 | 0x11 | 0x12 | 0x13 => {
-                panic!("unsupported cartridge type: MBC3");
-            }
-            0x19 | 0x1A | 0x1B | 0x1C | 0x1D | 0x1E => Cartridge {
-                mbc: Box::new(Mbc5::new(data)),
-            },
-            _ => {
-                // TODO: Add more MBC supports
-                panic!("unsupported cartridge type");
-            }
+        // Overdumps are accepted as they are, only truncated ROMs are rejected
+        let rom_size = match header.rom_size() {
+            Some(size) => size,
+            None => return Err(CartridgeError::UnknownRomSize(header.rom_size_code)),
+        };
+        if data.len() < rom_size {
+            return Err(CartridgeError::SizeMismatch {
+                expected: rom_size,
+                actual: data.len(),
+            });
         }
+
+        let ram_size = match header.ram_size() {
+            Some(size) => size,
+            None => return Err(CartridgeError::UnknownRamSize(header.ram_size_code)),
+        };
+
+        // Anything past the size stated in the header is never visible through the MBC
+        data.truncate(rom_size);
+
+        let cart_type = header.cartridge_type;
+        let mbc: Box<dyn MemoryBankController> = match cart_type {
+            0x00 | 0x08 | 0x09 => Box::new(Mbc0::new(data, ram_size)),
+            0x01..=0x03 => Box::new(Mbc1::new(data, ram_size)),
+            0x05 | 0x06 => Box::new(Mbc2::new(data)),
+            0x0B..=0x0D => Box::new(Mmm01::new(data, ram_size)),
+            0x0F | 0x10 => Box::new(Mbc3::new(data, ram_size, true)),
+            0x11..=0x13 => Box::new(Mbc3::new(data, ram_size, false)),
+            0x19..=0x1B => Box::new(Mbc5::new(data, ram_size, false)),
+            0x1C..=0x1E => Box::new(Mbc5::new(data, ram_size, true)),
+            0x20 => Box::new(Mbc6::new(data)),
+            0x22 => Box::new(Mbc7::new(data)),
+            0xFC => Box::new(PocketCamera::new(data)),
+            0xFD => Box::new(Tama5::new(data)),
+            0xFE => Box::new(Huc3::new(data, ram_size)),
+            0xFF => Box::new(Huc1::new(data, ram_size)),
+            _ => return Err(CartridgeError::UnsupportedType(cart_type)),
+        };
+
+        Ok(Cartridge {
+            mbc,
+            header,
+
+            has_battery: has_battery(cart_type),
+            ram_dirty: false,
+        })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
     }
 
     pub fn read(&self, addr: u16) -> u8 {
@@ -48,10 +111,297 @@ impl Cartridge {
 
     pub fn write(&mut self, addr: u16, data: u8) {
         self.mbc.write(addr, data);
+        if self.has_battery && self.mbc.take_dirty() {
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn step(&mut self, cycle: u8) {
+        self.mbc.step(cycle);
+        // The Pocket Camera stores captures in its RAM by itself
+        if self.has_battery && self.mbc.take_dirty() {
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.mbc.set_rtc_mode(mode);
+    }
+
+    // Tilt of the cartridge in g, positive towards the right and towards the player
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source);
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    pub fn is_ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    // Returns the battery-backed RAM in the raw .sav layout, and clears the dirty flag
+    pub fn export_ram(&mut self) -> Option<Vec<u8>> {
+        if !self.has_battery {
+            return None;
+        }
+
+        self.ram_dirty = false;
+        Some(self.mbc.dump_ram())
+    }
+
+    pub fn import_ram(&mut self, data: &[u8]) {
+        if !self.has_battery {
+            return;
+        }
+
+        self.mbc.restore_ram(data);
+        self.mbc.take_dirty();
+        self.ram_dirty = false;
     }
 }
 
 trait MemoryBankController {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
+
+    // Only cartridges with a clock on board care about these
+    fn step(&mut self, _cycle: u8) {}
+    fn set_rtc_mode(&mut self, _mode: RtcMode) {}
+
+    fn dump_ram(&self) -> Vec<u8> {
+        vec![]
+    }
+    fn restore_ram(&mut self, _data: &[u8]) {}
+    // Whether what `dump_ram` returns has changed since the last call, ignoring the clock
+    fn take_dirty(&mut self) -> bool;
+
+    // Only MBC7 has an accelerometer
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // Only the Pocket Camera has an image sensor
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+}
+
+fn has_battery(cart_type: u8) -> bool {
+    matches!(
+        cart_type,
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x20 | 0x22 | 0xFC | 0xFD | 0xFE | 0xFF
+    )
+}
+
+// MMM01 dumps usually start with the first game, while the menu and the header describing
+// the whole cartridge sit in the last 32 KiB
+fn mmm01_menu_header(data: &[u8]) -> Option<CartridgeHeader> {
+    let menu = data.len().checked_sub(2 * ROM_BANK_SIZE)?;
+    CartridgeHeader::parse(&data[menu..]).filter(|header| (0x0B..=0x0D).contains(&header.cartridge_type))
+}
+
+// Bank numbers are masked to the banks actually present, so banks beyond the end of
+// the ROM wrap around, as on real hardware
+fn read_rom_bank(rom: &[u8], bank: usize, addr: usize) -> u8 {
+    let bank = bank % (rom.len() / ROM_BANK_SIZE);
+    rom[bank * ROM_BANK_SIZE + (addr & (ROM_BANK_SIZE - 1))]
+}
+
+// RAM smaller than the addressed range is mirrored, and missing RAM reads as open bus
+fn read_ram_bank(ram: &[u8], bank: usize, addr: usize) -> u8 {
+    if ram.is_empty() {
+        return 0xFF;
+    }
+
+    ram[(bank * RAM_BANK_SIZE + (addr & (RAM_BANK_SIZE - 1))) % ram.len()]
+}
+
+// Returns whether the stored byte has changed
+fn write_ram_bank(ram: &mut [u8], bank: usize, addr: usize, data: u8) -> bool {
+    if ram.is_empty() {
+        return false;
+    }
+
+    let len = ram.len();
+    let byte = &mut ram[(bank * RAM_BANK_SIZE + (addr & (RAM_BANK_SIZE - 1))) % len];
+    let changed = *byte != data;
+    *byte = data;
+    changed
+}
+
+// Copies as much of a save file as fits, so that truncated or padded files still load
+fn restore_ram(ram: &mut [u8], data: &[u8]) {
+    let n = ram.len().min(data.len());
+    ram[..n].copy_from_slice(&data[..n]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::header::{CARTRIDGE_TYPE_ADDR, RAM_SIZE_ADDR, ROM_SIZE_ADDR};
+    use super::*;
+
+    #[test]
+    fn test_cartridge_battery_ram() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x03;
+        rom[RAM_SIZE_ADDR] = 0x02;
+
+        let mut cart = Cartridge::new(rom.clone()).unwrap();
+        assert!(cart.has_battery());
+        assert!(!cart.is_ram_dirty());
+
+        // Only writes that change the stored RAM count
+        cart.write(0xA010, 0xAB);
+        assert!(!cart.is_ram_dirty());
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA010, 0x00);
+        assert!(!cart.is_ram_dirty());
+        cart.write(0xA010, 0xAB);
+        assert!(cart.is_ram_dirty());
+
+        let save = cart.export_ram().unwrap();
+        assert!(!cart.is_ram_dirty());
+        assert_eq!(0xAB, save[0x10]);
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.import_ram(&save);
+        cart.write(0x0000, 0x0A);
+        assert_eq!(0xAB, cart.read(0xA010));
+    }
+
+    #[test]
+    fn test_cartridge_flash_dirty() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x20;
+
+        // Programs a byte of MBC6 flash, which never goes through 0xA000..=0xBFFF
+        let mut cart = Cartridge::new(rom).unwrap();
+        for &(addr, data) in &[
+            (0x0C00, 0x01),
+            (0x1000, 0x01),
+            (0x2800, 0x08),
+            (0x2000, 0x02),
+            (0x5555, 0xAA),
+            (0x2000, 0x01),
+            (0x4AAA, 0x55),
+            (0x2000, 0x02),
+            (0x5555, 0xA0),
+            (0x4000, 0x12),
+        ] {
+            cart.write(addr, data);
+        }
+        assert!(cart.is_ram_dirty());
+    }
+
+    #[test]
+    fn test_cartridge_without_battery() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x02;
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0xAB);
+        assert!(!cart.has_battery());
+        assert!(!cart.is_ram_dirty());
+        assert_eq!(None, cart.export_ram());
+    }
+
+    #[test]
+    fn test_cartridge_errors() {
+        assert_eq!(
+            Some(CartridgeError::TooSmall(0x100)),
+            Cartridge::new(vec![0x00; 0x100]).err()
+        );
+
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0xEE;
+        assert_eq!(Some(CartridgeError::UnsupportedType(0xEE)), Cartridge::new(rom).err());
+
+        let mut rom = vec![0x00; 0x8000];
+        rom[ROM_SIZE_ADDR] = 0x01;
+        assert_eq!(
+            Some(CartridgeError::SizeMismatch {
+                expected: 0x10000,
+                actual: 0x8000
+            }),
+            Cartridge::new(rom).err()
+        );
+    }
+
+    #[test]
+    fn test_cartridge_camera_ram() {
+        // The header claims no RAM, but captures still need the full 128 KiB
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0xFC;
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.write(0x4000, 0x10);
+        cart.write(0xA000, 0x01);
+        while cart.read(0xA000) & 0x01 != 0 {
+            cart.step(0xFF);
+        }
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x0F);
+        cart.write(0xBFFF, 0xAB);
+        assert_eq!(0xAB, cart.read(0xBFFF));
+    }
+
+    #[test]
+    fn test_cartridge_rom_bank_wrap() {
+        let mut rom = vec![0x00; 0x4000 * 8];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x19;
+        rom[ROM_SIZE_ADDR] = 0x01;
+        rom[0x4000 * 2] = 0xAB;
+        rom[0x4000 * 6] = 0xCD; // Overdumped, so never visible
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.write(0x2000, 0x06); // There are only 4 banks, so this is bank 2
+        assert_eq!(0xAB, cart.read(0x4000));
+    }
+
+    #[test]
+    fn test_cartridge_rtc_save() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x10;
+        rom[RAM_SIZE_ADDR] = 0x03;
+
+        let mut cart = Cartridge::new(rom).unwrap();
+        assert_eq!(0x8000 + 48, cart.export_ram().unwrap().len());
+
+        // Selecting and writing an RTC register leaves the RAM alone
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x08);
+        cart.write(0xA000, 0x12);
+        assert!(!cart.is_ram_dirty());
+    }
+
+    #[test]
+    fn test_cartridge_mmm01_menu_header() {
+        let mut rom = vec![0x00; 0x4000 * 8];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x01; // Header of the first game
+        rom[0x18000 + CARTRIDGE_TYPE_ADDR] = 0x0D;
+        rom[0x18000 + ROM_SIZE_ADDR] = 0x02;
+        rom[0x18000 + RAM_SIZE_ADDR] = 0x03;
+
+        let cart = Cartridge::new(rom).unwrap();
+        assert_eq!(0x0D, cart.header().cartridge_type);
+        assert!(cart.has_battery());
+    }
+
+    #[test]
+    fn test_cartridge_ram_size() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[CARTRIDGE_TYPE_ADDR] = 0x1B;
+        rom[RAM_SIZE_ADDR] = 0x01;
+
+        // 2 KiB of RAM is mirrored throughout every bank
+        let mut cart = Cartridge::new(rom).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0xAB);
+        cart.write(0x4000, 0x03);
+        assert_eq!(0xAB, cart.read(0xA800));
+        assert_eq!(0x800, cart.export_ram().unwrap().len());
+    }
 }
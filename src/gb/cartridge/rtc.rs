@@ -0,0 +1,279 @@
+use super::clock::{Clock, RtcMode};
+
+// Size of the RTC footer appended to .sav files (the layout shared by BGB and VBA-M)
+pub const RTC_SAVE_SIZE: usize = 48;
+
+pub struct Rtc {
+    clock: Clock,
+
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+
+    latched: [u8; 5],
+    latch_armed: bool,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            clock: Clock::new(),
+
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+
+            latched: [0x00; 5],
+            latch_armed: false,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        self.clock.set_mode(mode);
+    }
+
+    pub fn step(&mut self, cycle: u8) {
+        if self.halted {
+            return;
+        }
+
+        let secs = self.clock.step(cycle);
+        self.advance(secs);
+    }
+
+    // Writing 0x00 and then 0x01 copies the running clock into the latched registers
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_armed && data == 0x01 {
+            self.sync();
+            self.latched = self.registers();
+        }
+        self.latch_armed = data == 0x00;
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08..=0x0C => self.latched[(reg - 0x08) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8) {
+        self.sync();
+
+        match reg {
+            0x08 => {
+                self.seconds = data & 0x3F;
+                self.clock.reset_cycles();
+            }
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as u16,
+            0x0C => {
+                self.days = (self.days & 0x0FF) | ((data as u16 & 0x01) << 8);
+                self.halted = data & 0x40 != 0;
+                self.day_carry = data & 0x80 != 0;
+            }
+            _ => (),
+        };
+    }
+
+    // The footer consists of the running and latched registers as 32-bit little-endian
+    // words, followed by the UNIX time they were taken at (0 when running on emulated time)
+    pub fn dump(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for v in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*v as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&self.clock.timestamp().to_le_bytes());
+        data
+    }
+
+    pub fn restore(&mut self, data: &[u8]) {
+        if data.len() < 44 {
+            return;
+        }
+
+        let word = |i: usize| data[i * 4];
+        self.seconds = word(0) & 0x3F;
+        self.minutes = word(1) & 0x3F;
+        self.hours = word(2) & 0x1F;
+        self.days = word(3) as u16 | (word(4) as u16 & 0x01) << 8;
+        self.halted = word(4) & 0x40 != 0;
+        self.day_carry = word(4) & 0x80 != 0;
+        for (i, v) in self.latched.iter_mut().enumerate() {
+            *v = word(5 + i);
+        }
+        self.clock.reset_cycles();
+
+        // Some emulators only store the lower 32 bits of the timestamp
+        let mut timestamp = [0x00; 8];
+        let n = (data.len() - 40).min(8);
+        timestamp[..n].copy_from_slice(&data[40..40 + n]);
+        self.clock.restore_timestamp(u64::from_le_bytes(timestamp));
+        self.sync();
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8 | (self.halted as u8) << 6 | (self.day_carry as u8) << 7,
+        ]
+    }
+
+    fn sync(&mut self) {
+        let secs = self.clock.sync();
+        if !self.halted {
+            self.advance(secs);
+        }
+    }
+
+    fn advance(&mut self, mut secs: u64) {
+        // Registers holding out-of-range values have to be ticked one by one
+        while secs > 0 && !self.is_normalized() {
+            self.tick();
+            secs -= 1;
+        }
+        if secs == 0 {
+            return;
+        }
+
+        let total =
+            self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600 + self.days as u64 * 86400 + secs;
+        let days = total / 86400;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.days = (days & 0x1FF) as u16;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+    }
+
+    fn is_normalized(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::clock::CLOCK_HZ;
+    use super::*;
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn test_rtc_tick() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+
+        for _ in 0..(CLOCK_HZ / 4) {
+            rtc.step(4);
+        }
+        assert_eq!(0x00, rtc.read(0x08)); // Not latched yet
+
+        latch(&mut rtc);
+        assert_eq!(0, rtc.read(0x08));
+        assert_eq!(0, rtc.read(0x09));
+        assert_eq!(0, rtc.read(0x0A));
+        assert_eq!(0, rtc.read(0x0B));
+        assert_eq!(0x80, rtc.read(0x0C));
+    }
+
+    #[test]
+    fn test_rtc_halt() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0C, 0x40);
+
+        for _ in 0..(CLOCK_HZ / 4) {
+            rtc.step(4);
+        }
+        latch(&mut rtc);
+        assert_eq!(0, rtc.read(0x08));
+        assert_eq!(0x40, rtc.read(0x0C));
+    }
+
+    #[test]
+    fn test_rtc_out_of_range() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 62);
+        rtc.advance(3);
+
+        latch(&mut rtc);
+        assert_eq!(1, rtc.read(0x08));
+        assert_eq!(0, rtc.read(0x09));
+    }
+
+    #[test]
+    fn test_rtc_dump_restore() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 12);
+        rtc.write(0x0B, 0x34);
+        rtc.write(0x0C, 0x81);
+        latch(&mut rtc);
+
+        let data = rtc.dump();
+        assert_eq!(RTC_SAVE_SIZE, data.len());
+
+        let mut restored = Rtc::new();
+        restored.restore(&data);
+        assert_eq!(12, restored.read(0x08));
+        assert_eq!(0x34, restored.read(0x0B));
+        assert_eq!(0x81, restored.read(0x0C));
+        assert_eq!(rtc.registers(), restored.registers());
+    }
+
+    #[test]
+    fn test_rtc_advance() {
+        let mut rtc = Rtc::new();
+        rtc.advance(86400 * 2 + 3600 * 5 + 60 * 7 + 11);
+
+        latch(&mut rtc);
+        assert_eq!(11, rtc.read(0x08));
+        assert_eq!(7, rtc.read(0x09));
+        assert_eq!(5, rtc.read(0x0A));
+        assert_eq!(2, rtc.read(0x0B));
+        assert_eq!(0, rtc.read(0x0C));
+    }
+}
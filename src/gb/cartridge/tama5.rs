@@ -0,0 +1,334 @@
+use super::clock::{Clock, RtcMode};
+use super::{read_rom_bank, restore_ram, MemoryBankController};
+
+const TAMA5_RAM_SIZE: usize = 0x20;
+
+// Size of the RTC footer appended to .sav files: timestamp, then seconds, minutes, hours,
+// weekday, day, month and year
+const TAMA5_RTC_SAVE_SIZE: usize = 15;
+
+// Registers selected through 0xA001
+const ROM_BANK_LOW: usize = 0x0;
+const ROM_BANK_HIGH: usize = 0x1;
+const DATA_LOW: usize = 0x4;
+const DATA_HIGH: usize = 0x5;
+const COMMAND: usize = 0x6;
+const ADDRESS: usize = 0x7;
+const READY: usize = 0xA;
+const OUT_LOW: usize = 0xC;
+const OUT_HIGH: usize = 0xD;
+
+pub struct Tama5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+
+    // Everything goes through nibble-wide registers at 0xA000 selected by 0xA001
+    register: usize,
+    registers: [u8; 0x10],
+
+    clock: Clock,
+    rtc: Calendar,
+
+    dirty: bool,
+}
+
+impl Tama5 {
+    pub fn new(data: Vec<u8>) -> Self {
+        Tama5 {
+            rom: data,
+            ram: vec![0x00; TAMA5_RAM_SIZE],
+
+            register: 0,
+            registers: [0x00; 0x10],
+
+            clock: Clock::new(),
+            rtc: Calendar::new(),
+
+            dirty: false,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.registers[ROM_BANK_HIGH] & 0x01) << 4 | self.registers[ROM_BANK_LOW]) as usize
+    }
+
+    // Writing the low address nibble carries out the command latched in the upper bits
+    // of the high address register
+    fn execute(&mut self) {
+        let addr = ((self.registers[COMMAND] & 0x01) << 4 | self.registers[ADDRESS]) as usize;
+        let data = self.registers[DATA_HIGH] << 4 | self.registers[DATA_LOW];
+
+        let out = match self.registers[COMMAND] >> 1 {
+            0x0 => {
+                self.dirty |= self.ram[addr] != data;
+                self.ram[addr] = data;
+                data
+            }
+            0x1 => self.ram[addr],
+            0x2 => {
+                self.sync();
+                self.rtc.write_nibble(addr & 0x0F, data & 0x0F);
+                self.clock.reset_cycles();
+                data
+            }
+            0x3 => {
+                self.sync();
+                self.rtc.read_nibble(addr & 0x0F)
+            }
+            _ => 0x00,
+        };
+        self.registers[OUT_LOW] = out & 0x0F;
+        self.registers[OUT_HIGH] = out >> 4;
+    }
+
+    fn sync(&mut self) {
+        let secs = self.clock.sync();
+        self.rtc.advance(secs);
+    }
+}
+
+impl MemoryBankController for Tama5 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr],
+            0x4000..=0x7FFF => read_rom_bank(&self.rom, self.rom_bank(), addr),
+            0xA000..=0xBFFF => {
+                if addr & 0x01 != 0 {
+                    return 0xFF;
+                }
+
+                match self.register {
+                    // Commands complete immediately
+                    READY => 0xF1,
+                    OUT_LOW | OUT_HIGH => 0xF0 | self.registers[self.register],
+                    _ => 0xFF,
+                }
+            }
+            _ => panic!("inaccessible address"),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000..=0x7FFF => (),
+            0xA000..=0xBFFF => {
+                if addr & 0x01 != 0 {
+                    self.register = (data & 0x0F) as usize;
+                    return;
+                }
+
+                self.registers[self.register] = data & 0x0F;
+                if self.register == ADDRESS {
+                    self.execute();
+                }
+            }
+            _ => panic!("inaccessible address"),
+        };
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.clock.timestamp().to_le_bytes());
+        data.extend_from_slice(&self.rtc.dump());
+        data
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) {
+        restore_ram(&mut self.ram, data);
+
+        if data.len() != TAMA5_RAM_SIZE + TAMA5_RTC_SAVE_SIZE {
+            return;
+        }
+        let rtc = &data[TAMA5_RAM_SIZE..];
+
+        let mut timestamp = [0x00; 8];
+        timestamp.copy_from_slice(&rtc[0..8]);
+        self.rtc.restore(&rtc[8..]);
+
+        self.clock.restore_timestamp(u64::from_le_bytes(timestamp));
+        self.sync();
+    }
+
+    fn step(&mut self, cycle: u8) {
+        let secs = self.clock.step(cycle);
+        self.rtc.advance(secs);
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.clock.set_mode(mode);
+    }
+}
+
+// TC8521-style calendar, read and written as BCD nibbles
+struct Calendar {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    weekday: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+impl Calendar {
+    fn new() -> Self {
+        Calendar {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            weekday: 0,
+            day: 1,
+            month: 1,
+            year: 0,
+        }
+    }
+
+    fn read_nibble(&self, index: usize) -> u8 {
+        match index {
+            0x0 => self.seconds % 10,
+            0x1 => self.seconds / 10,
+            0x2 => self.minutes % 10,
+            0x3 => self.minutes / 10,
+            0x4 => self.hours % 10,
+            0x5 => self.hours / 10,
+            0x6 => self.weekday,
+            0x7 => self.day % 10,
+            0x8 => self.day / 10,
+            0x9 => self.month % 10,
+            0xA => self.month / 10,
+            0xB => self.year % 10,
+            0xC => self.year / 10,
+            _ => 0x00,
+        }
+    }
+
+    fn write_nibble(&mut self, index: usize, v: u8) {
+        let units = |dst: &mut u8, max: u8| *dst = (*dst / 10 * 10 + v.min(9)).min(max);
+        let tens = |dst: &mut u8, max: u8| *dst = (v.min(9) * 10 + *dst % 10).min(max);
+
+        match index {
+            0x0 => units(&mut self.seconds, 59),
+            0x1 => tens(&mut self.seconds, 59),
+            0x2 => units(&mut self.minutes, 59),
+            0x3 => tens(&mut self.minutes, 59),
+            0x4 => units(&mut self.hours, 23),
+            0x5 => tens(&mut self.hours, 23),
+            0x6 => self.weekday = v % 7,
+            0x7 => units(&mut self.day, 31),
+            0x8 => tens(&mut self.day, 31),
+            0x9 => units(&mut self.month, 12),
+            0xA => tens(&mut self.month, 12),
+            0xB => units(&mut self.year, 99),
+            0xC => tens(&mut self.year, 99),
+            _ => (),
+        };
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn advance(&mut self, secs: u64) {
+        let total = self.seconds as u64 + secs;
+        self.seconds = (total % 60) as u8;
+
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+
+        for _ in 0..total / 24 {
+            self.weekday = (self.weekday + 1) % 7;
+            self.day += 1;
+            if self.day > self.days_in_month() {
+                self.day = 1;
+                self.month += 1;
+            }
+            if self.month > 12 {
+                self.month = 1;
+                self.year = (self.year + 1) % 100;
+            }
+        }
+    }
+
+    fn dump(&self) -> [u8; 7] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.weekday,
+            self.day,
+            self.month,
+            self.year,
+        ]
+    }
+
+    fn restore(&mut self, data: &[u8]) {
+        self.seconds = data[0].min(59);
+        self.minutes = data[1].min(59);
+        self.hours = data[2].min(23);
+        self.weekday = data[3] % 7;
+        self.day = data[4].clamp(1, 31);
+        self.month = data[5].clamp(1, 12);
+        self.year = data[6] % 100;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_register(mbc: &mut Tama5, register: u8, data: u8) {
+        mbc.write(0xA001, register);
+        mbc.write(0xA000, data);
+    }
+
+    fn read_register(mbc: &mut Tama5, register: u8) -> u8 {
+        mbc.write(0xA001, register);
+        mbc.read(0xA000) & 0x0F
+    }
+
+    fn command(mbc: &mut Tama5, command: u8, addr: u8, data: u8) -> u8 {
+        write_register(mbc, 0x4, data & 0x0F);
+        write_register(mbc, 0x5, data >> 4);
+        write_register(mbc, 0x6, command << 1 | addr >> 4);
+        write_register(mbc, 0x7, addr & 0x0F);
+        read_register(mbc, 0xD) << 4 | read_register(mbc, 0xC)
+    }
+
+    #[test]
+    fn test_tama5_ram() {
+        let mut mbc = Tama5::new(vec![0x00; 0x8000]);
+        assert_eq!(0x01, read_register(&mut mbc, 0xA));
+
+        command(&mut mbc, 0x0, 0x13, 0xAB);
+        assert_eq!(0xAB, command(&mut mbc, 0x1, 0x13, 0x00));
+        assert_eq!(0xAB, mbc.dump_ram()[0x13]);
+    }
+
+    #[test]
+    fn test_tama5_rtc() {
+        let mut mbc = Tama5::new(vec![0x00; 0x8000]);
+
+        // 23:59:59 on 28/02/01
+        for (index, nibble) in [9, 5, 9, 5, 3, 2, 0, 8, 2, 2, 0, 1, 0].iter().enumerate() {
+            command(&mut mbc, 0x2, index as u8, *nibble);
+        }
+        mbc.rtc.advance(1);
+
+        let nibbles: Vec<u8> = (0..13).map(|i| command(&mut mbc, 0x3, i, 0x00)).collect();
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 1, 1, 0, 3, 0, 1, 0], nibbles);
+    }
+}
@@ -93,6 +93,16 @@ impl Cpu {
         self.state.PC = 0x0100;
         self.state.SP = 0xFFFE;
     }
+
+    // Jumps to `addr` with `a` in the A register, as if it had been called from `return_addr`
+    pub fn simulate_call<B: Bus>(&mut self, bus: &mut B, addr: u16, a: u8, sp: u16, return_addr: u16) {
+        self.state.A = a;
+        self.state.SP = sp.wrapping_sub(2);
+        bus.write16(self.state.SP, return_addr);
+        self.state.PC = addr;
+        self.state.interrupted = false;
+        self.state.halted = false;
+    }
 }
 
 impl fmt::Debug for Cpu {
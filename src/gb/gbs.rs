@@ -0,0 +1,274 @@
+use super::cartridge::{Cartridge, CartridgeError};
+use std::error;
+use std::fmt;
+
+const MAGIC: &[u8] = b"GBS";
+const HEADER_SIZE: usize = 0x70;
+
+const MIN_ROM_SIZE: usize = 0x8000;
+// The most MBC5 can address
+const MAX_ROM_SIZE: usize = 0x800000;
+
+// The player code lives below the load address, in front of a regular cartridge header
+const VBLANK_VECTOR: usize = 0x0040;
+const TIMER_VECTOR: usize = 0x0050;
+pub const IDLE_ADDR: u16 = 0x0070;
+const MIN_LOAD_ADDR: u16 = 0x0150;
+
+// MBC5+RAM, which maps every bank number as written and gives the music code work RAM.
+// The RAM still has to be enabled, see `GameBoy::load_gbs`.
+const CARTRIDGE_TYPE: u8 = 0x1A;
+const RAM_SIZE_CODE: u8 = 0x02;
+
+#[derive(Debug, PartialEq)]
+pub enum GbsError {
+    NotGbs,
+    UnsupportedVersion(u8),
+    // The header or the code area ends early
+    Truncated,
+    InvalidLoadAddress(u16),
+    TooLarge(usize),
+    NoSuchSong(u8),
+    Cartridge(CartridgeError),
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::GbsError::*;
+
+        match *self {
+            NotGbs => write!(f, "not a GBS file"),
+            UnsupportedVersion(version) => write!(f, "unsupported GBS version: {}", version),
+            Truncated => write!(f, "GBS file is truncated"),
+            InvalidLoadAddress(addr) => write!(f, "invalid load address: 0x{:04X}", addr),
+            TooLarge(size) => write!(f, "music code does not fit in a cartridge ({} bytes)", size),
+            NoSuchSong(song) => write!(f, "no such song: {}", song + 1),
+            Cartridge(ref err) => write!(f, "failed to build the cartridge: {}", err),
+        }
+    }
+}
+
+impl error::Error for GbsError {}
+
+pub fn is_gbs(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// Game Boy Sound file, holding the music code ripped from a game
+#[derive(Debug, Clone)]
+pub struct Gbs {
+    pub song_count: u8,
+    // 0-based, unlike in the file
+    pub first_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+
+    code: Vec<u8>,
+}
+
+impl Gbs {
+    pub fn parse(data: &[u8]) -> Result<Self, GbsError> {
+        if !is_gbs(data) {
+            return Err(GbsError::NotGbs);
+        }
+        if data.len() <= HEADER_SIZE {
+            return Err(GbsError::Truncated);
+        }
+        if data[0x03] != 1 {
+            return Err(GbsError::UnsupportedVersion(data[0x03]));
+        }
+
+        let read16 = |i: usize| data[i] as u16 | (data[i + 1] as u16) << 8;
+        let read_string = |i: usize| {
+            let field = &data[i..i + 0x20];
+            let len = field.iter().position(|&b| b == 0x00).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).into_owned()
+        };
+
+        let load_addr = read16(0x06);
+        let code = data[HEADER_SIZE..].to_vec();
+        if !(MIN_LOAD_ADDR..0x8000).contains(&load_addr) {
+            return Err(GbsError::InvalidLoadAddress(load_addr));
+        }
+        if load_addr as usize + code.len() > MAX_ROM_SIZE {
+            return Err(GbsError::TooLarge(code.len()));
+        }
+
+        Ok(Gbs {
+            song_count: data[0x04],
+            first_song: data[0x05].saturating_sub(1),
+            load_addr,
+            init_addr: read16(0x08),
+            play_addr: read16(0x0A),
+            stack_pointer: read16(0x0C),
+            timer_modulo: data[0x0E],
+            timer_control: data[0x0F],
+
+            title: read_string(0x10),
+            author: read_string(0x30),
+            copyright: read_string(0x50),
+
+            code,
+        })
+    }
+
+    // PLAY is called from the timer interrupt instead of VBlank when the timer is enabled
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+
+    // Builds a cartridge with the code at its load address, and a small player in the first bank.
+    // INIT has to be called separately, see `GameBoy::load_gbs`.
+    pub fn build_cartridge(&self) -> Result<Cartridge, GbsError> {
+        let end = self.load_addr as usize + self.code.len();
+        let size = end.next_power_of_two().max(MIN_ROM_SIZE);
+        let mut rom = vec![0xFF; size];
+        rom[self.load_addr as usize..end].copy_from_slice(&self.code);
+
+        // RST instructions are relative to the load address
+        for vector in (0x00..0x40).step_by(8) {
+            write_jump(&mut rom, vector, self.load_addr + vector as u16);
+        }
+
+        let (vblank, timer) = if self.uses_timer() {
+            (None, Some(self.play_addr))
+        } else {
+            (Some(self.play_addr), None)
+        };
+        write_handler(&mut rom, VBLANK_VECTOR, vblank);
+        write_handler(&mut rom, 0x0048, None);
+        write_handler(&mut rom, TIMER_VECTOR, timer);
+        write_handler(&mut rom, 0x0058, None);
+        write_handler(&mut rom, 0x0060, None);
+
+        // INIT returns here, then the CPU waits for interrupts forever
+        let idle = IDLE_ADDR as usize;
+        rom[idle..idle + 4].copy_from_slice(&[
+            0xFB, // EI
+            0x76, // HALT
+            0x18, 0xFD, // JR -3
+        ]);
+
+        write_header(&mut rom, &self.title, size);
+        Cartridge::new(rom).map_err(GbsError::Cartridge)
+    }
+}
+
+impl fmt::Display for Gbs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Title:     {}", self.title)?;
+        writeln!(f, "Author:    {}", self.author)?;
+        writeln!(f, "Copyright: {}", self.copyright)?;
+        write!(f, "Songs:     {} (first: {})", self.song_count, self.first_song + 1)
+    }
+}
+
+fn write_jump(rom: &mut [u8], addr: usize, target: u16) {
+    rom[addr..addr + 3].copy_from_slice(&[0xC3, target as u8, (target >> 8) as u8]);
+}
+
+// An interrupt handler that calls `target` if there is one, then returns
+fn write_handler(rom: &mut [u8], addr: usize, target: Option<u16>) {
+    match target {
+        Some(target) => {
+            rom[addr..addr + 4].copy_from_slice(&[0xCD, target as u8, (target >> 8) as u8, 0xD9]);
+        }
+        None => rom[addr] = 0xD9,
+    }
+}
+
+fn write_header(rom: &mut [u8], title: &str, size: usize) {
+    let title: Vec<u8> = title
+        .bytes()
+        .filter(|b| b.is_ascii_graphic() || *b == b' ')
+        .take(15)
+        .collect();
+    rom[0x0134..0x0144].iter_mut().for_each(|b| *b = 0x00);
+    rom[0x0134..0x0134 + title.len()].copy_from_slice(&title);
+
+    rom[0x0147] = CARTRIDGE_TYPE;
+    rom[0x0148] = (size / MIN_ROM_SIZE).trailing_zeros() as u8;
+    rom[0x0149] = RAM_SIZE_CODE;
+    rom[0x014A..0x014D].iter_mut().for_each(|b| *b = 0x00);
+    rom[0x014D] = rom[0x0134..0x014D]
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::Bus;
+    use super::super::GameBoy;
+    use super::*;
+
+    fn build_gbs(timer_control: u8) -> Vec<u8> {
+        let mut data = vec![0x00; HEADER_SIZE];
+        data[0..4].copy_from_slice(b"GBS\x01");
+        data[0x04] = 3;
+        data[0x05] = 2;
+        data[0x06..0x0E].copy_from_slice(&[0x00, 0x04, 0x00, 0x04, 0x10, 0x04, 0xFE, 0xFF]);
+        data[0x0F] = timer_control;
+        data[0x10..0x14].copy_from_slice(b"Tune");
+        data.extend_from_slice(&[0xC9; 0x20]);
+        data
+    }
+
+    #[test]
+    fn test_gbs_parse() {
+        let gbs = Gbs::parse(&build_gbs(0x00)).unwrap();
+        assert_eq!(3, gbs.song_count);
+        assert_eq!(1, gbs.first_song);
+        assert_eq!((0x0400, 0x0400, 0x0410), (gbs.load_addr, gbs.init_addr, gbs.play_addr));
+        assert_eq!(0xFFFE, gbs.stack_pointer);
+        assert_eq!("Tune", gbs.title);
+        assert!(!gbs.uses_timer());
+
+        assert_eq!(GbsError::NotGbs, Gbs::parse(b"PATCH").unwrap_err());
+        let mut data = build_gbs(0x00);
+        data[0x06] = 0x00;
+        data[0x07] = 0x00;
+        assert_eq!(GbsError::InvalidLoadAddress(0x0000), Gbs::parse(&data).unwrap_err());
+    }
+
+    #[test]
+    fn test_gbs_cartridge() {
+        let cart = Gbs::parse(&build_gbs(0x04)).unwrap().build_cartridge().unwrap();
+        assert_eq!("Tune", cart.header().title);
+        assert_eq!(0xC3, cart.read(0x0008));
+        assert_eq!(0x0408, cart.read(0x0009) as u16 | (cart.read(0x000A) as u16) << 8);
+        assert_eq!(0xD9, cart.read(VBLANK_VECTOR as u16));
+        assert_eq!(
+            [0xCD, 0x10, 0x04, 0xD9],
+            [0, 1, 2, 3].map(|i| cart.read(TIMER_VECTOR as u16 + i))
+        );
+        assert_eq!(0xC9, cart.read(0x0400));
+    }
+
+    #[test]
+    fn test_gbs_cartridge_ram() {
+        // PLAY increments a counter kept in cartridge RAM
+        let mut data = build_gbs(0x00);
+        data[HEADER_SIZE + 0x10..HEADER_SIZE + 0x18].copy_from_slice(&[
+            0xFA, 0x00, 0xA0, // LD A, (0xA000)
+            0x3C, // INC A
+            0xEA, 0x00, 0xA0, // LD (0xA000), A
+            0xC9, // RET
+        ]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_gbs(&Gbs::parse(&data).unwrap(), 0).unwrap();
+        gameboy.unpause();
+        for _ in 0..3 {
+            gameboy.step();
+        }
+        assert!((2..=4).contains(&gameboy.mmu.read8(0xA000)));
+    }
+}
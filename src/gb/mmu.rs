@@ -1,3 +1,4 @@
+use super::apu::Apu;
 use super::bus::Bus;
 use super::cartridge::Cartridge;
 use super::ram::Ram;
@@ -5,6 +6,7 @@ use super::ram::Ram;
 pub struct Mmu {
     state: State,
     cart: Cartridge,
+    apu: Apu,
     memory: Ram,
 }
 
@@ -12,7 +14,8 @@ impl Mmu {
     pub fn new() -> Self {
         Mmu {
             state: State::new(),
-            cart: Cartridge::new(vec![0x00; 1 << 15]),
+            cart: Cartridge::new(vec![0x00; 1 << 15]).unwrap(),
+            apu: Apu::new(),
             memory: Ram::new(vec![0x00; 1 << 16]),
         }
     }
@@ -27,29 +30,32 @@ impl Mmu {
         self.cart = cart;
     }
 
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn step(&mut self, cycle: u8) {
+        self.cart.step(cycle);
+    }
+
     pub fn simulate_bootloader(&mut self) {
         self.memory = Ram::new(vec![0x00; 1 << 16]);
         self.memory.write8(0xFF05, 0x00);
         self.memory.write8(0xFF06, 0x00);
         self.memory.write8(0xFF07, 0x00);
-        self.memory.write8(0xFF10, 0x80);
-        self.memory.write8(0xFF11, 0xBF);
-        self.memory.write8(0xFF12, 0xF3);
-        self.memory.write8(0xFF14, 0xBF);
-        self.memory.write8(0xFF16, 0x3F);
-        self.memory.write8(0xFF17, 0x00);
-        self.memory.write8(0xFF19, 0xBF);
-        self.memory.write8(0xFF1A, 0x7F);
-        self.memory.write8(0xFF1B, 0xFF);
-        self.memory.write8(0xFF1C, 0x9F);
-        self.memory.write8(0xFF1E, 0xBF);
-        self.memory.write8(0xFF20, 0xFF);
-        self.memory.write8(0xFF21, 0x00);
-        self.memory.write8(0xFF22, 0x00);
-        self.memory.write8(0xFF23, 0xBF);
-        self.memory.write8(0xFF24, 0x77);
-        self.memory.write8(0xFF25, 0xF3);
-        self.memory.write8(0xFF26, 0xF1);
+        self.apu.simulate_bootloader();
         self.memory.write8(0xFF40, 0x91);
         self.memory.write8(0xFF42, 0x00);
         self.memory.write8(0xFF43, 0x00);
@@ -83,6 +89,36 @@ impl Mmu {
         self.state.joypad_requested = false;
     }
 
+    // The CPU goes through this view of the bus, while the PPU and debuggers use the Mmu directly
+    pub fn cpu_bus(&mut self) -> CpuBus<'_> {
+        CpuBus { mmu: self }
+    }
+
+    // With restrictions off, the CPU can access VRAM and OAM in any PPU mode
+    pub fn set_access_restricted(&mut self, v: bool) {
+        self.state.access_restricted = v;
+    }
+
+    fn is_accessible(&self, addr: u16) -> bool {
+        if !self.state.access_restricted {
+            return true;
+        }
+
+        let mode = self.memory.read8(0xFF41) & 0x03;
+        match addr {
+            // VRAM is used by the PPU in mode 3
+            0x8000..=0x9FFF => mode != 3,
+            // OAM is used by the PPU in modes 2 and 3
+            0xFE00..=0xFE9F => mode < 2,
+            _ => true,
+        }
+    }
+
+    // Whether the CPU has written to STAT since the last call
+    pub fn take_stat_write(&mut self) -> bool {
+        std::mem::take(&mut self.state.stat_written)
+    }
+
     fn dma_transfer(&mut self, value: u8) {
         let start_addr = (value as u16) * 0x100;
         for i in 0..0xA0 {
@@ -100,6 +136,9 @@ impl Bus for Mmu {
             // Mirror of 0xC000..=0xDDFF (Typically not used)
             0xE000..=0xFDFF => self.memory.read8(addr - 0x2000),
 
+            // Sound registers and wave RAM
+            0xFF10..=0xFF3F => self.apu.read(addr),
+
             _ => self.memory.read8(addr),
         }
     }
@@ -129,6 +168,9 @@ impl Bus for Mmu {
                 // TODO: Should we reset divider's counter as well...?
                 self.memory.write8(addr, 0);
             }
+            // Sound registers and wave RAM
+            0xFF10..=0xFF3F => self.apu.write(addr, data),
+
             // DMA transfer
             0xFF46 => self.dma_transfer(data),
 
@@ -144,12 +186,120 @@ impl Bus for Mmu {
 
 struct State {
     joypad_requested: bool,
+    stat_written: bool,
+    access_restricted: bool,
 }
 
 impl State {
     fn new() -> Self {
         State {
             joypad_requested: false,
+            stat_written: false,
+            access_restricted: true,
         }
     }
 }
+
+// Reads from VRAM and OAM return 0xFF while the PPU is using them, and writes are dropped
+pub struct CpuBus<'a> {
+    mmu: &'a mut Mmu,
+}
+
+impl<'a> Bus for CpuBus<'a> {
+    fn read8(&self, addr: u16) -> u8 {
+        if self.mmu.is_accessible(addr) {
+            self.mmu.read8(addr)
+        } else {
+            0xFF
+        }
+    }
+
+    fn read16(&self, addr: u16) -> u16 {
+        self.read8(addr) as u16 | (self.read8(addr.wrapping_add(1)) as u16) << 8
+    }
+
+    fn write8(&mut self, addr: u16, data: u8) {
+        if !self.mmu.is_accessible(addr) {
+            return;
+        }
+
+        // The mode and coincidence bits of STAT are only written by the PPU
+        if addr == 0xFF41 {
+            let status = (data & 0x78) | (self.mmu.memory.read8(addr) & 0x07);
+            self.mmu.memory.write8(addr, status);
+            self.mmu.state.stat_written = true;
+        } else {
+            self.mmu.write8(addr, data);
+        }
+    }
+
+    fn write16(&mut self, addr: u16, data: u16) {
+        self.write8(addr, (data & 0xFF) as u8);
+        self.write8(addr.wrapping_add(1), (data >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ppu::Ppu;
+    use super::*;
+
+    #[test]
+    fn test_mmu_stat() {
+        let mut mmu = Mmu::new();
+        mmu.write8(0xFF40, 0x91);
+        let mut ppu = Ppu::new();
+
+        let mut modes = [0; 4];
+        for _ in 0..70224 {
+            ppu.step(&mut mmu, 1);
+            modes[(mmu.read8(0xFF41) & 0x03) as usize] += 1;
+        }
+        assert_eq!([144 * 204, 10 * 456, 144 * 80, 144 * 172], modes);
+        // No STAT source is enabled, and the PPU's own writes are not the CPU's
+        assert_eq!(0x00, mmu.read8(0xFF0F) & 0x02);
+        assert!(!mmu.take_stat_write());
+
+        let status = mmu.read8(0xFF41);
+        mmu.cpu_bus().write8(0xFF41, 0x0F);
+        assert_eq!(0x08 | (status & 0x07), mmu.read8(0xFF41));
+        assert!(mmu.take_stat_write());
+    }
+
+    #[test]
+    fn test_mmu_access_restrictions() {
+        let mut mmu = Mmu::new();
+        mmu.write8(0xFF40, 0x91);
+        mmu.write8(0x8000, 0x12);
+        mmu.write8(0xFE00, 0x34);
+        let mut ppu = Ppu::new();
+
+        // Mode 2
+        ppu.step(&mut mmu, 1);
+        assert_eq!(0x02, mmu.read8(0xFF41) & 0x03);
+        assert_eq!(0x12, mmu.cpu_bus().read8(0x8000));
+        assert_eq!(0xFF, mmu.cpu_bus().read8(0xFE00));
+        mmu.cpu_bus().write8(0xFE00, 0x56);
+        assert_eq!(0x34, mmu.read8(0xFE00));
+
+        // Mode 3
+        ppu.step(&mut mmu, 80);
+        assert_eq!(0x03, mmu.read8(0xFF41) & 0x03);
+        assert_eq!(0xFF, mmu.cpu_bus().read8(0x8000));
+        assert_eq!(0xFF, mmu.cpu_bus().read8(0xFE00));
+        mmu.cpu_bus().write8(0x8000, 0x56);
+        assert_eq!(0x12, mmu.read8(0x8000));
+
+        mmu.set_access_restricted(false);
+        mmu.cpu_bus().write8(0x8000, 0x56);
+        assert_eq!(0x56, mmu.cpu_bus().read8(0x8000));
+        assert_eq!(0x34, mmu.cpu_bus().read8(0xFE00));
+        mmu.set_access_restricted(true);
+
+        // HBlank
+        ppu.step(&mut mmu, 200);
+        assert_eq!(0x00, mmu.read8(0xFF41) & 0x03);
+        mmu.cpu_bus().write8(0xFE00, 0x56);
+        assert_eq!(0x56, mmu.cpu_bus().read8(0xFE00));
+    }
+}
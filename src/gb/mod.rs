@@ -1,7 +1,11 @@
 #![allow(dead_code)]
 
+pub mod apu;
+pub mod archive;
 pub mod cartridge;
+pub mod gbs;
 pub mod joypad;
+pub mod patch;
 pub mod screen;
 
 // TODO: The followings should be private in the future
@@ -9,18 +13,26 @@ pub mod cpu;
 pub mod mmu;
 pub mod ppu;
 pub mod timer;
+pub mod wav;
 
 mod bus;
+mod crc;
 mod interrupt;
 mod ram;
 
-use self::cartridge::Cartridge;
+use self::apu::Channel;
+use self::bus::Bus;
+use self::cartridge::{Cartridge, ImageSource};
 use self::cpu::Cpu;
+use self::gbs::{Gbs, GbsError};
 use self::joypad::{Button, Joypad};
 use self::mmu::Mmu;
-use self::ppu::Ppu;
+use self::ppu::{Ppu, RenderMode};
 use self::screen::Screen;
 use self::timer::Timer;
+use self::wav::WavRecorder;
+
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
 
 pub struct GameBoy {
     cpu: Cpu,
@@ -31,6 +43,8 @@ pub struct GameBoy {
     joypad: Joypad,
 
     paused: bool,
+    samples: Vec<f32>,
+    recorder: Option<WavRecorder>,
 }
 
 impl GameBoy {
@@ -44,27 +58,55 @@ impl GameBoy {
             joypad: Joypad::new(),
 
             paused: true,
+            samples: vec![],
+            recorder: None,
         }
     }
 
     pub fn load(&mut self, cart: Cartridge) {
         self.cpu.simulate_bootloader();
+        let render_mode = self.ppu.render_mode();
         self.ppu = Ppu::new();
+        self.ppu.set_render_mode(render_mode);
         self.mmu.simulate_bootloader();
         self.mmu.load_cartridge(cart);
         self.timer = Timer::new();
         self.joypad = Joypad::new();
     }
 
+    // Loads a GBS file and calls its INIT routine for `song`, after which PLAY runs on every
+    // VBlank or timer interrupt
+    pub fn load_gbs(&mut self, gbs: &Gbs, song: u8) -> Result<(), GbsError> {
+        if song >= gbs.song_count {
+            return Err(GbsError::NoSuchSong(song));
+        }
+
+        self.load(gbs.build_cartridge()?);
+        // Rips expect their work RAM to be usable from the start
+        self.mmu.write8(0x0000, 0x0A);
+        self.mmu.write8(0xFF06, gbs.timer_modulo);
+        self.mmu.write8(0xFF07, gbs.timer_control & 0x07);
+        self.mmu.write8(0xFF0F, 0x00);
+        self.mmu.write8(0xFFFF, if gbs.uses_timer() { 0x04 } else { 0x01 });
+        self.cpu
+            .simulate_call(&mut self.mmu, gbs.init_addr, song, gbs.stack_pointer, gbs::IDLE_ADDR);
+        Ok(())
+    }
+
     pub fn step(&mut self) -> Vec<u8> {
         if self.paused {
             return self.screen.dump();
         }
 
         loop {
-            let cycle = self.cpu.step(&mut self.mmu);
+            let cycle = self.cpu.step(&mut self.mmu.cpu_bus());
+            if self.mmu.take_stat_write() {
+                self.ppu.on_stat_write(&mut self.mmu);
+            }
             self.ppu.step(&mut self.mmu, cycle);
             self.timer.step(&mut self.mmu, cycle);
+            self.mmu.apu_mut().step(cycle);
+            self.mmu.step(cycle);
 
             if self.mmu.is_joypad_state_requested() {
                 self.mmu.receive_joypad_state(self.joypad.transfer_state());
@@ -74,6 +116,13 @@ impl GameBoy {
             }
         }
 
+        self.mmu.apu_mut().end_frame();
+        let samples = self.mmu.apu_mut().take_samples();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.push(&samples);
+        }
+        self.samples.extend(samples);
+
         self.screen.refresh(&self.ppu.transfer_screen());
         self.screen.dump()
     }
@@ -86,6 +135,91 @@ impl GameBoy {
         self.paused = false;
     }
 
+    pub fn is_ram_dirty(&self) -> bool {
+        self.mmu.cartridge().is_ram_dirty()
+    }
+
+    pub fn export_ram(&mut self) -> Option<Vec<u8>> {
+        self.mmu.cartridge_mut().export_ram()
+    }
+
+    pub fn import_ram(&mut self, data: &[u8]) {
+        self.mmu.cartridge_mut().import_ram(data);
+    }
+
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mmu.cartridge_mut().set_tilt(x, y);
+    }
+
+    pub fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mmu.cartridge_mut().set_image_source(source);
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.ppu.set_render_mode(mode);
+    }
+
+    // VRAM and OAM are locked to the CPU while the PPU uses them, unless turned off for debugging
+    pub fn set_access_restricted(&mut self, v: bool) {
+        self.mmu.set_access_restricted(v);
+    }
+
+    // Audio is only produced once a sample rate has been set, e.g. 44100 or 48000
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.mmu.apu_mut().set_sample_rate(rate);
+    }
+
+    // Drains the samples produced by the last frames, interleaved as left and right
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn take_samples_i16(&mut self) -> Vec<i16> {
+        self.take_samples()
+            .iter()
+            .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            .collect()
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.mmu.apu_mut().set_channel_muted(channel, muted);
+    }
+
+    pub fn set_solo_channel(&mut self, channel: Option<Channel>) {
+        self.mmu.apu_mut().set_solo_channel(channel);
+    }
+
+    // Also produces each channel's output before mixing, see `take_channel_samples`
+    pub fn set_unmixed_output(&mut self, enabled: bool) {
+        self.mmu.apu_mut().set_unmixed_output(enabled);
+    }
+
+    // Drains the unmixed samples, interleaved in the order of `Channel`
+    pub fn take_channel_samples(&mut self) -> Vec<f32> {
+        self.mmu.apu_mut().take_channel_samples()
+    }
+
+    // Starts recording the audio output, at 44100 Hz unless a sample rate has been set
+    pub fn start_recording(&mut self) {
+        let rate = match self.mmu.apu().sample_rate() {
+            Some(rate) => rate,
+            None => {
+                self.set_sample_rate(DEFAULT_SAMPLE_RATE);
+                DEFAULT_SAMPLE_RATE
+            }
+        };
+        self.recorder = Some(WavRecorder::new(rate));
+    }
+
+    // Returns the recording as a WAV file
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        self.recorder.take().map(|recorder| recorder.finish())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
     pub fn press(&mut self, button: Button) {
         self.joypad.press(&mut self.mmu, button);
     }
@@ -0,0 +1,442 @@
+use super::crc::crc32;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: u32 = 0x454F46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// UPS and BPS both end with the CRC32 of the source, the target and the patch itself
+const FOOTER_SIZE: usize = 12;
+
+// Well beyond the largest Game Boy ROM, and small enough to allocate without trusting the patch
+const MAX_TARGET_SIZE: usize = 8 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    UnknownFormat,
+    // The patch ends in the middle of a record
+    Truncated,
+    // A record refers to data outside of the source or the target
+    OutOfBounds,
+    TooLarge(usize),
+    PatchChecksumMismatch,
+    SourceChecksumMismatch { expected: u32, actual: u32 },
+    TargetChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::PatchError::*;
+
+        match *self {
+            UnknownFormat => write!(f, "unknown patch format (expected IPS, UPS or BPS)"),
+            Truncated => write!(f, "patch is truncated"),
+            OutOfBounds => write!(f, "patch refers to data outside of the ROM"),
+            TooLarge(size) => write!(f, "patched ROM would be too large ({} bytes)", size),
+            PatchChecksumMismatch => write!(f, "patch is corrupted (checksum mismatch)"),
+            SourceChecksumMismatch { expected, actual } => write!(
+                f,
+                "patch is for a different ROM (expected CRC32 {:08X}, got {:08X})",
+                expected, actual
+            ),
+            TargetChecksumMismatch { expected, actual } => write!(
+                f,
+                "patched ROM is not what the patch expects (expected CRC32 {:08X}, got {:08X})",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+// Applies an IPS, UPS or BPS patch, picked by its magic number
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+// Checks whether the given file name looks like a patch rather than a ROM
+pub fn is_patch_file_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".ips") || name.ends_with(".ups") || name.ends_with(".bps")
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    let mut out = rom.to_vec();
+
+    loop {
+        let offset = reader.read_be(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = offset as usize;
+
+        let size = reader.read_be(2)? as usize;
+        let (size, data) = if size == 0 {
+            // Run-length encoded record
+            let size = reader.read_be(2)? as usize;
+            let value = reader.read_u8()?;
+            (size, vec![value; size])
+        } else {
+            (size, reader.read_bytes(size)?.to_vec())
+        };
+
+        let end = offset + size;
+        if end > MAX_TARGET_SIZE {
+            return Err(PatchError::TooLarge(end));
+        }
+        if out.len() < end {
+            out.resize(end, 0x00);
+        }
+        out[offset..end].copy_from_slice(&data);
+    }
+
+    // Some patches append the size to truncate the ROM to
+    if let Ok(size) = reader.read_be(3) {
+        out.truncate(size as usize);
+    }
+
+    Ok(out)
+}
+
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let body = verify_footer(rom, patch)?;
+    let mut reader = Reader::new(body, UPS_MAGIC.len());
+
+    let _source_size = reader.read_varint()?;
+    let target_size = check_target_size(reader.read_varint()?)?;
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0x00);
+
+    // Hunks skip ahead and then XOR the bytes up to and including a terminating zero
+    let mut pos: usize = 0;
+    while !reader.is_empty() {
+        pos = pos.checked_add(reader.read_varint()?).ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let x = reader.read_u8()?;
+            if pos < out.len() {
+                out[pos] ^= x;
+            }
+            pos = pos.checked_add(1).ok_or(PatchError::OutOfBounds)?;
+            if x == 0x00 {
+                break;
+            }
+        }
+    }
+
+    verify_target(patch, &out)?;
+    Ok(out)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let body = verify_footer(rom, patch)?;
+    let mut reader = Reader::new(body, BPS_MAGIC.len());
+
+    let _source_size = reader.read_varint()?;
+    let target_size = check_target_size(reader.read_varint()?)?;
+    let metadata_size = reader.read_varint()?;
+    reader.read_bytes(metadata_size)?;
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_pos: isize = 0;
+    let mut target_pos: isize = 0;
+
+    while !reader.is_empty() {
+        let action = reader.read_varint()?;
+        let length = (action >> 2) + 1;
+        // Nothing may be written past the target size, which also bounds every length below
+        if length > target_size - out.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+
+        match action & 0x03 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(source_range(rom, start, length)?);
+            }
+            // TargetRead
+            1 => out.extend_from_slice(reader.read_bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_pos = offset_pos(source_pos, reader.read_signed_varint()?)?;
+                let start = usize::try_from(source_pos).map_err(|_| PatchError::OutOfBounds)?;
+                out.extend_from_slice(source_range(rom, start, length)?);
+                source_pos = offset_pos(source_pos, length as isize)?;
+            }
+            // TargetCopy, which may overlap with what it is writing
+            _ => {
+                target_pos = offset_pos(target_pos, reader.read_signed_varint()?)?;
+                for _ in 0..length {
+                    let byte = usize::try_from(target_pos)
+                        .ok()
+                        .and_then(|i| out.get(i).copied())
+                        .ok_or(PatchError::OutOfBounds)?;
+                    out.push(byte);
+                    target_pos = offset_pos(target_pos, 1)?;
+                }
+            }
+        };
+    }
+
+    if out.len() != target_size {
+        return Err(PatchError::OutOfBounds);
+    }
+    verify_target(patch, &out)?;
+    Ok(out)
+}
+
+fn check_target_size(size: usize) -> Result<usize, PatchError> {
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::TooLarge(size));
+    }
+    Ok(size)
+}
+
+fn source_range(rom: &[u8], start: usize, length: usize) -> Result<&[u8], PatchError> {
+    let end = start.checked_add(length).ok_or(PatchError::OutOfBounds)?;
+    rom.get(start..end).ok_or(PatchError::OutOfBounds)
+}
+
+fn offset_pos(pos: isize, offset: isize) -> Result<isize, PatchError> {
+    pos.checked_add(offset).ok_or(PatchError::OutOfBounds)
+}
+
+// Checks the patch and source checksums, and returns the patch without its footer
+fn verify_footer<'a>(rom: &[u8], patch: &'a [u8]) -> Result<&'a [u8], PatchError> {
+    if patch.len() < FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+
+    if crc32(&patch[..patch.len() - 4]) != read_le32(&footer[8..]) {
+        return Err(PatchError::PatchChecksumMismatch);
+    }
+
+    let expected = read_le32(&footer[0..]);
+    let actual = crc32(rom);
+    if expected != actual {
+        return Err(PatchError::SourceChecksumMismatch { expected, actual });
+    }
+
+    Ok(body)
+}
+
+fn verify_target(patch: &[u8], out: &[u8]) -> Result<(), PatchError> {
+    let expected = read_le32(&patch[patch.len() - 8..]);
+    let actual = crc32(out);
+    if expected != actual {
+        return Err(PatchError::TargetChecksumMismatch { expected, actual });
+    }
+
+    Ok(())
+}
+
+fn read_le32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Reader { data, pos }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PatchError> {
+        let byte = *self.data.get(self.pos).ok_or(PatchError::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.pos.checked_add(n).ok_or(PatchError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(PatchError::Truncated)?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn read_be(&mut self, n: usize) -> Result<u32, PatchError> {
+        let bytes = self.read_bytes(n)?;
+        Ok(bytes.iter().fold(0, |acc, &b| acc << 8 | b as u32))
+    }
+
+    // Variable-length numbers shared by UPS and BPS, where each continuation also adds one
+    // so that every number has a single encoding
+    fn read_varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.read_u8()?;
+            value = value
+                .checked_add((x & 0x7F) as usize * shift)
+                .ok_or(PatchError::OutOfBounds)?;
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    fn read_signed_varint(&mut self) -> Result<isize, PatchError> {
+        let value = self.read_varint()?;
+        let magnitude = (value >> 1) as isize;
+        Ok(if value & 0x01 != 0 { -magnitude } else { magnitude })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let x = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(0x80 | x);
+                return;
+            }
+            out.push(x);
+            value -= 1;
+        }
+    }
+
+    fn append_footer(patch: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn test_apply_ips() {
+        let rom = vec![0x00; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAB, 0xCD]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0xEE]); // RLE past the end
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            vec![0x00, 0x00, 0xAB, 0xCD, 0x00, 0x00, 0x00, 0x00, 0xEE, 0xEE, 0xEE],
+            apply(&rom, &patch).unwrap()
+        );
+        assert_eq!(Err(PatchError::Truncated), apply(&rom, &patch[..patch.len() - 1]));
+    }
+
+    #[test]
+    fn test_apply_ups() {
+        let rom = vec![0x10, 0x20, 0x30, 0x40];
+        let target = vec![0x10, 0x21, 0x30, 0x40, 0x50];
+
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        encode_varint(1, &mut patch);
+        patch.extend_from_slice(&[0x01, 0x00]);
+        encode_varint(1, &mut patch);
+        patch.extend_from_slice(&[0x50, 0x00]);
+        append_footer(&mut patch, &rom, &target);
+
+        assert_eq!(target, apply(&rom, &patch).unwrap());
+        assert_eq!(
+            Err(PatchError::SourceChecksumMismatch {
+                expected: crc32(&rom),
+                actual: crc32(&target)
+            }),
+            apply(&target, &patch)
+        );
+    }
+
+    #[test]
+    fn test_apply_bps() {
+        let rom = b"ABCDEFGH".to_vec();
+        let target = b"ABCDxyxyxEFGH".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        encode_varint(0, &mut patch);
+        encode_varint((4 - 1) << 2, &mut patch); // SourceRead "ABCD"
+        encode_varint((2 - 1) << 2 | 1, &mut patch); // TargetRead "xy"
+        patch.extend_from_slice(b"xy");
+        encode_varint((3 - 1) << 2 | 3, &mut patch); // TargetCopy "xyx" from offset 4
+        encode_varint(4 << 1, &mut patch);
+        encode_varint((4 - 1) << 2 | 2, &mut patch); // SourceCopy "EFGH" from offset 4
+        encode_varint(4 << 1, &mut patch);
+        append_footer(&mut patch, &rom, &target);
+
+        assert_eq!(target, apply(&rom, &patch).unwrap());
+
+        let last = patch.len() - 1;
+        patch[last] ^= 0xFF;
+        assert_eq!(Err(PatchError::PatchChecksumMismatch), apply(&rom, &patch));
+    }
+
+    #[test]
+    fn test_malformed_patches() {
+        let rom = vec![0x00; 8];
+
+        // IPS records can reach 16 MiB
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0xFF, 0xFF, 0xF0, 0x00, 0x00, 0xFF, 0xFF, 0xEE]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(Err(PatchError::TooLarge(0xFFFFF0 + 0xFFFF)), apply(&rom, &patch));
+
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(1 << 40, &mut patch);
+        append_footer(&mut patch, &rom, &rom);
+        assert_eq!(Err(PatchError::TooLarge(1 << 40)), apply(&rom, &patch));
+
+        // A hunk that skips to the very end of the address space
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(usize::MAX, &mut patch);
+        patch.extend_from_slice(&[0x01, 0x00]);
+        append_footer(&mut patch, &rom, &rom);
+        assert_eq!(Err(PatchError::OutOfBounds), apply(&rom, &patch));
+
+        // An overlapping TargetCopy that would repeat one byte forever, and a SourceCopy from far
+        // outside the source
+        let mut copies = vec![];
+        encode_varint(1, &mut copies); // TargetRead "x"
+        copies.push(b'x');
+        encode_varint((usize::MAX >> 2) << 2 | 3, &mut copies);
+        encode_varint(0, &mut copies);
+        let mut far = vec![];
+        encode_varint(2, &mut far);
+        encode_varint(usize::MAX - 1, &mut far);
+        for actions in &[copies, far] {
+            let mut patch = b"BPS1".to_vec();
+            encode_varint(rom.len(), &mut patch);
+            encode_varint(rom.len(), &mut patch);
+            encode_varint(0, &mut patch);
+            patch.extend_from_slice(actions);
+            append_footer(&mut patch, &rom, &rom);
+            assert_eq!(Err(PatchError::OutOfBounds), apply(&rom, &patch));
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use super::super::bus::Bus;
+use super::super::screen::{FrameBuffer, Pixel, SCREEN_W};
+use super::register::{LCDControl, Register::*};
+use super::renderer::{get_color_number, get_rgb};
+use super::sprite::Sprite;
+use std::collections::VecDeque;
+
+// The first tile fetch of every line is thrown away, which along with the dot needed to start
+// the pipeline makes mode 3 last at least 172 dots
+const INITIAL_DELAY: u8 = 7;
+// Fetching a sprite stalls the pipeline for 6 dots, plus up to 5 while the background fetch finishes
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    color: u8,
+    obp1: bool,
+    behind_bg: bool,
+}
+
+const TRANSPARENT: SpritePixel = SpritePixel {
+    color: 0,
+    obp1: false,
+    behind_bg: false,
+};
+
+// Background and window tile fetcher, where each of its first three steps takes 2 dots
+struct Fetcher {
+    window: bool,
+    ticks: u8,
+    // The tile has been fetched, and waits for the FIFO to empty
+    ready: bool,
+    tile_x: u8,
+    tile_n: u8,
+    low: u8,
+    high: u8,
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Self {
+        Fetcher {
+            window,
+            ticks: 0,
+            ready: false,
+            tile_x: 0,
+            tile_n: 0,
+            low: 0,
+            high: 0,
+        }
+    }
+}
+
+struct SpriteFetch {
+    sprite: Sprite,
+    remaining: u8,
+}
+
+// Renders a line dot by dot, through a background FIFO and a sprite FIFO, so that register
+// writes in the middle of mode 3 take effect from the next pixel on
+pub struct PixelFifo {
+    ly: u8,
+    x: u8,
+    delay: u8,
+    // SCX % 8 pixels are dropped at the start of the line
+    discard: u8,
+    // Row of the window for this line, if WY has been reached in this frame
+    window_line: Option<u8>,
+
+    bg: VecDeque<u8>,
+    sprites: VecDeque<SpritePixel>,
+    fetcher: Fetcher,
+
+    pending_sprites: Vec<Sprite>,
+    sprite_fetch: Option<SpriteFetch>,
+    // Background tile whose fetch has already delayed a sprite
+    penalized_tile: Option<u8>,
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        PixelFifo {
+            ly: 0,
+            x: 0,
+            delay: 0,
+            discard: 0,
+            window_line: None,
+
+            bg: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(false),
+
+            pending_sprites: vec![],
+            sprite_fetch: None,
+            penalized_tile: None,
+        }
+    }
+
+    // `sprites` are the ones found by the OAM scan for this line
+    pub fn start_line<B: Bus>(&mut self, bus: &mut B, ly: u8, sprites: Vec<Sprite>, window_line: Option<u8>) {
+        self.ly = ly;
+        self.x = 0;
+        self.delay = INITIAL_DELAY;
+        self.discard = SCX.read(bus) % 8;
+        self.window_line = window_line;
+
+        self.bg.clear();
+        self.sprites.clear();
+        self.fetcher = Fetcher::new(false);
+
+        self.pending_sprites = sprites;
+        self.sprite_fetch = None;
+        self.penalized_tile = None;
+    }
+
+    pub fn is_line_done(&self) -> bool {
+        self.x == SCREEN_W
+    }
+
+    pub fn is_window_drawn(&self) -> bool {
+        self.fetcher.window
+    }
+
+    // Advances by one dot of mode 3
+    pub fn tick<B: Bus>(&mut self, frame_buffer: &mut FrameBuffer, bus: &mut B) {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        let control = LCDControl::new(LCDC.read(bus));
+
+        if let Some(fetch) = self.sprite_fetch.as_mut() {
+            fetch.remaining -= 1;
+            if fetch.remaining > 0 {
+                return;
+            }
+            let sprite = fetch.sprite;
+            self.sprite_fetch = None;
+            self.merge_sprite(bus, sprite, control);
+        }
+
+        // Switching to the window restarts the fetch, after a dot of its own
+        if (self.discard == 0 || self.x == 0) && !self.fetcher.window && self.window_reached(bus, control) {
+            self.bg.clear();
+            self.fetcher = Fetcher::new(true);
+            // With WX below 7 the window starts left of the screen, which cuts off its first pixels
+            self.discard = if self.x == 0 {
+                7u8.saturating_sub(WX.read(bus))
+            } else {
+                0
+            };
+            return;
+        }
+
+        if self.discard == 0 && control.obj_enabled() && self.start_sprite_fetch(bus) {
+            return;
+        }
+
+        self.tick_fetcher(bus, control);
+
+        let bg_color = match self.bg.pop_front() {
+            Some(color) => color,
+            None => return,
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let sprite = self.sprites.pop_front().unwrap_or(TRANSPARENT);
+
+        // With LCDC.0 cleared, the background and window turn white, but sprites are still drawn
+        let bg_color = if control.bgwin_enabled() { bg_color } else { 0 };
+        let sprite_visible = control.obj_enabled() && sprite.color != 0 && !(sprite.behind_bg && bg_color != 0);
+        let (r, g, b) = if sprite_visible {
+            let palette = if sprite.obp1 { OBP1.read(bus) } else { OBP0.read(bus) };
+            get_rgb(palette, sprite.color)
+        } else {
+            get_rgb(BGP.read(bus), bg_color)
+        };
+        frame_buffer.set_pixel(self.x, self.ly, Pixel(r, g, b, 255));
+
+        self.x += 1;
+    }
+
+    fn window_reached<B: Bus>(&self, bus: &mut B, control: LCDControl) -> bool {
+        control.win_enabled() && self.window_line.is_some() && self.x + 7 >= WX.read(bus)
+    }
+
+    fn start_sprite_fetch<B: Bus>(&mut self, bus: &mut B) -> bool {
+        let x = self.x;
+        let i = match self.pending_sprites.iter().position(|s| s.x <= x + 8) {
+            Some(i) => i,
+            None => return false,
+        };
+        let sprite = self.pending_sprites.remove(i);
+
+        // The sprite has to wait for the background tile under its leftmost pixel, unless that
+        // tile has already held up another sprite
+        let wait = if sprite.x == 0 {
+            5
+        } else {
+            let scroll = if self.fetcher.window { 0 } else { SCX.read(bus) };
+            let pixel = sprite.x.saturating_sub(8).wrapping_add(scroll);
+            let tile = pixel / 8;
+            if self.penalized_tile == Some(tile) {
+                0
+            } else {
+                self.penalized_tile = Some(tile);
+                5u8.saturating_sub(pixel % 8)
+            }
+        };
+
+        self.sprite_fetch = Some(SpriteFetch {
+            sprite,
+            remaining: SPRITE_FETCH_DOTS + wait,
+        });
+        true
+    }
+
+    // Sprite pixels only replace transparent ones, so the sprite fetched first wins, which is the
+    // one with the lower X
+    fn merge_sprite<B: Bus>(&mut self, bus: &mut B, sprite: Sprite, control: LCDControl) {
+        let addr = sprite.tile_row_addr(self.ly, control.obj_height());
+        let low = bus.read8(addr);
+        let high = bus.read8(addr + 1);
+
+        while self.sprites.len() < 8 {
+            self.sprites.push_back(TRANSPARENT);
+        }
+        for i in 0..8 {
+            // Sprites partly off the left edge start before the current pixel
+            let slot = sprite.x as i16 - 8 + i as i16 - self.x as i16;
+            if slot < 0 {
+                continue;
+            }
+
+            let bit = if sprite.x_flip() { i } else { 7 - i };
+            let pixel = &mut self.sprites[slot as usize];
+            if pixel.color == 0 {
+                *pixel = SpritePixel {
+                    color: get_color_number(bit, low, high),
+                    obp1: sprite.uses_obp1(),
+                    behind_bg: sprite.behind_bg(),
+                };
+            }
+        }
+    }
+
+    fn tick_fetcher<B: Bus>(&mut self, bus: &mut B, control: LCDControl) {
+        if !self.fetcher.ready {
+            self.fetcher.ticks += 1;
+            match self.fetcher.ticks {
+                2 => {
+                    let addr = self.tile_map_addr(bus, control);
+                    self.fetcher.tile_n = bus.read8(addr);
+                }
+                4 => {
+                    let addr = self.tile_data_addr(bus, control);
+                    self.fetcher.low = bus.read8(addr);
+                }
+                6 => {
+                    let addr = self.tile_data_addr(bus, control);
+                    self.fetcher.high = bus.read8(addr + 1);
+                    self.fetcher.ready = true;
+                }
+                _ => (),
+            };
+        }
+
+        if self.fetcher.ready && self.bg.is_empty() {
+            for bit in (0..8).rev() {
+                self.bg
+                    .push_back(get_color_number(bit, self.fetcher.low, self.fetcher.high));
+            }
+            self.fetcher.ticks = 0;
+            self.fetcher.ready = false;
+            self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+        }
+    }
+
+    fn tile_map_addr<B: Bus>(&self, bus: &mut B, control: LCDControl) -> u16 {
+        let (map_loc, col, row) = if self.fetcher.window {
+            let line = self.window_line.unwrap_or(0);
+            (control.win_map_loc(), self.fetcher.tile_x, line / 8)
+        } else {
+            let col = (SCX.read(bus) / 8).wrapping_add(self.fetcher.tile_x);
+            let row = self.ly.wrapping_add(SCY.read(bus)) / 8;
+            (control.bg_map_loc(), col, row)
+        };
+        map_loc + (row as u16 % 32) * 32 + (col as u16 % 32)
+    }
+
+    fn tile_data_addr<B: Bus>(&self, bus: &mut B, control: LCDControl) -> u16 {
+        let fine_y = if self.fetcher.window {
+            self.window_line.unwrap_or(0) % 8
+        } else {
+            self.ly.wrapping_add(SCY.read(bus)) % 8
+        };
+
+        let tiles_loc = control.bgwin_tile_loc();
+        let tile_n = if tiles_loc == 0x8800 {
+            (self.fetcher.tile_n as i8 as i16 + 128) as u16
+        } else {
+            self.fetcher.tile_n as u16
+        };
+        tiles_loc + tile_n * 16 + fine_y as u16 * 2
+    }
+}
@@ -1,13 +1,25 @@
+mod fifo;
 mod register;
 mod renderer;
+mod sprite;
 
-use self::register::{LCDStatus, Register::*};
+use self::fifo::PixelFifo;
+use self::register::{LCDControl, LCDStatus, Register::*};
 use self::renderer::Renderer;
 use super::bus::Bus;
 use super::interrupt::{self, Interrupt};
 use super::screen::{FrameBuffer, SCREEN_H};
 
 const ONE_CYCLE: u16 = 456;
+const ONE_FRAME: u32 = ONE_CYCLE as u32 * 154;
+// The first line after the LCD is turned on is a few dots shorter, and skips the OAM scan, so STAT
+// reports mode 0 until mode 3
+const FIRST_LINE_START: u16 = 4;
+const OAM_SCAN_END: u16 = 80;
+// Mode 3 has a fixed length with the scanline renderer
+const SCANLINE_TRANSFER_END: u16 = 252;
+// Past this WX the window starts beyond the right edge of the screen
+const WX_MAX: u8 = 166;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Mode {
@@ -17,8 +29,18 @@ pub enum Mode {
     VRAMRead, // Mode 3
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RenderMode {
+    // Draws each line at once when mode 3 starts, which is fast but ignores mid-line changes
+    Scanline,
+    // Fetches pixels dot by dot, so mode 3 varies in length with SCX, the window and sprites
+    PixelFifo,
+}
+
 pub struct Ppu {
     state: State,
+    render_mode: RenderMode,
+    fifo: PixelFifo,
     screen: FrameBuffer,
     screen_buffer: FrameBuffer,
 }
@@ -27,85 +49,194 @@ impl Ppu {
     pub fn new() -> Self {
         Ppu {
             state: State::new(),
+            render_mode: RenderMode::PixelFifo,
+            fifo: PixelFifo::new(),
             screen: FrameBuffer::new(),
             screen_buffer: FrameBuffer::new(),
         }
     }
 
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.render_mode = mode;
+    }
+
     pub fn step<B: Bus>(&mut self, bus: &mut B, cycle: u8) {
-        self.update_lcd_status(bus);
+        for _ in 0..cycle {
+            self.tick(bus);
+        }
+    }
 
-        {
-            self.state.clock += cycle as u16;
-            if self.state.clock < ONE_CYCLE {
-                return;
+    fn tick<B: Bus>(&mut self, bus: &mut B) {
+        if !LCDControl::new(LCDC.read(bus)).lcd_enabled() {
+            if self.state.lcd_enabled {
+                self.turn_off(bus);
             }
-            self.state.clock -= ONE_CYCLE;
+            self.tick_off();
+            return;
+        }
+        if !self.state.lcd_enabled {
+            self.turn_on();
+        }
+
+        let cur_line = LY.read(bus);
+        if cur_line < SCREEN_H {
+            self.update_mode(bus, cur_line);
+        } else {
+            self.state.mode = Mode::VBlank;
         }
+        self.update_lcd_status(bus);
+
+        self.state.clock += 1;
+        if self.state.clock < ONE_CYCLE {
+            return;
+        }
+        self.state.clock = 0;
 
         {
             let mut next_line = LY.read(bus) + 1;
             if next_line == SCREEN_H {
                 interrupt::request(bus, Interrupt::VBlank);
             } else if next_line > SCREEN_H + 9 {
-                self.screen = self.screen_buffer;
+                // The frame in progress when the LCD was turned on is not shown
+                self.screen = if self.state.first_frame {
+                    renderer::blank_frame()
+                } else {
+                    self.screen_buffer
+                };
                 self.state.screen_prepared = true;
+                self.state.first_frame = false;
+                self.state.window_triggered = false;
+                self.state.window_line = 0;
 
                 next_line = 0;
             }
             LY.write(bus, next_line);
-
-            self.state.line_drawn = false;
         }
     }
 
-    fn update_lcd_status<B: Bus>(&mut self, bus: &mut B) {
+    fn turn_off<B: Bus>(&mut self, bus: &mut B) {
+        self.state = State {
+            lcd_enabled: false,
+            mode: Mode::HBlank,
+            screen_prepared: self.state.screen_prepared,
+            ..State::new()
+        };
+        LY.write(bus, 0);
         let mut status = LCDStatus::new(STAT.read(bus));
+        status.set_mode(Mode::HBlank);
+        STAT.write(bus, status.raw());
 
-        let cur_line = LY.read(bus);
-        let cur_mode = status.mode();
+        self.screen_buffer = renderer::blank_frame();
+    }
 
-        let interrupt = if cur_line >= SCREEN_H {
-            status.set_mode(Mode::VBlank);
-            status.vblank_interrupt_enabled()
-        } else {
-            match self.state.clock {
-                0..=79 => {
-                    status.set_mode(Mode::OAMRead);
-                    status.oam_interrupt_enabled()
-                }
-                80..=251 => {
-                    if !self.state.line_drawn {
-                        let mut renderer = Renderer::new(&mut self.screen_buffer, bus);
-                        renderer.render_scanline();
-                        self.state.line_drawn = true;
-                    }
+    // Frames keep coming at the usual rate while the LCD is off, but stay blank
+    fn tick_off(&mut self) {
+        self.state.off_dots += 1;
+        if self.state.off_dots == ONE_FRAME {
+            self.state.off_dots = 0;
+            self.screen = self.screen_buffer;
+            self.state.screen_prepared = true;
+        }
+    }
 
-                    status.set_mode(Mode::VRAMRead);
-                    false
+    fn turn_on(&mut self) {
+        self.state = State {
+            clock: FIRST_LINE_START,
+            mode: Mode::HBlank,
+            first_frame: true,
+            screen_prepared: self.state.screen_prepared,
+            ..State::new()
+        };
+    }
+
+    fn update_mode<B: Bus>(&mut self, bus: &mut B, cur_line: u8) {
+        match self.state.clock {
+            0 => self.state.mode = Mode::OAMRead,
+            OAM_SCAN_END => {
+                self.state.mode = Mode::VRAMRead;
+                // Once LY has matched WY, the window can show up on any later line of the frame
+                if cur_line == WY.read(bus) {
+                    self.state.window_triggered = true;
                 }
-                _ => {
-                    status.set_mode(Mode::HBlank);
-                    status.hblank_interrupt_enabled()
+                let window_line = if self.state.window_triggered {
+                    Some(self.state.window_line)
+                } else {
+                    None
+                };
+                match self.render_mode {
+                    RenderMode::Scanline => {
+                        let control = LCDControl::new(LCDC.read(bus));
+                        let window_line = window_line.filter(|_| control.win_enabled() && WX.read(bus) <= WX_MAX);
+                        Renderer::new(&mut self.screen_buffer, bus).render_scanline(window_line);
+                        if window_line.is_some() {
+                            self.state.window_line += 1;
+                        }
+                    }
+                    RenderMode::PixelFifo => {
+                        let height = LCDControl::new(LCDC.read(bus)).obj_height();
+                        let sprites = sprite::scan_oam(bus, cur_line, height);
+                        self.fifo.start_line(bus, cur_line, sprites, window_line);
+                    }
                 }
             }
+            _ => (),
         };
 
-        if interrupt && status.mode() != cur_mode {
-            interrupt::request(bus, Interrupt::LCDStat);
+        if self.state.mode != Mode::VRAMRead {
+            return;
         }
-
-        if cur_line == LYC.read(bus) {
-            status.set_lyc_coincidence(true);
-
-            if status.lyc_coincidence_interrupt_enabled() {
-                interrupt::request(bus, Interrupt::LCDStat);
+        let done = match self.render_mode {
+            RenderMode::Scanline => self.state.clock >= SCANLINE_TRANSFER_END,
+            RenderMode::PixelFifo => self.fifo.is_line_done(),
+        };
+        if done {
+            self.state.mode = Mode::HBlank;
+            if self.render_mode == RenderMode::PixelFifo && self.fifo.is_window_drawn() {
+                self.state.window_line += 1;
             }
-        } else {
-            status.set_lyc_coincidence(false);
+        } else if self.render_mode == RenderMode::PixelFifo {
+            self.fifo.tick(&mut self.screen_buffer, bus);
         }
+    }
 
+    fn update_lcd_status<B: Bus>(&mut self, bus: &mut B) {
+        let mut status = LCDStatus::new(STAT.read(bus));
+        status.set_mode(self.state.mode);
+        status.set_lyc_coincidence(LY.read(bus) == LYC.read(bus));
         STAT.write(bus, status.raw());
+
+        self.update_stat_line(bus, status);
+    }
+
+    // All the sources of the STAT interrupt are OR-ed into one line, and the interrupt is only
+    // requested when that line goes high
+    fn update_stat_line<B: Bus>(&mut self, bus: &mut B, status: LCDStatus) {
+        let mode_source = match self.state.mode {
+            Mode::HBlank => status.hblank_interrupt_enabled(),
+            Mode::VBlank => status.vblank_interrupt_enabled(),
+            Mode::OAMRead => status.oam_interrupt_enabled(),
+            Mode::VRAMRead => false,
+        };
+        let line = mode_source || (status.lyc_coincidence() && status.lyc_coincidence_interrupt_enabled());
+
+        if line && !self.state.stat_line {
+            interrupt::request(bus, Interrupt::LCDStat);
+        }
+        self.state.stat_line = line;
+    }
+
+    // On DMG, every STAT source is enabled for a moment while STAT is written, which can request
+    // an interrupt even if the new value enables none of them
+    pub fn on_stat_write<B: Bus>(&mut self, bus: &mut B) {
+        if !self.state.lcd_enabled {
+            return;
+        }
+        let status = LCDStatus::new(STAT.read(bus) | 0b0111_1000);
+        self.update_stat_line(bus, status);
     }
 
     pub fn is_screen_prepared(&self) -> bool {
@@ -123,17 +254,254 @@ impl Ppu {
 }
 
 pub struct State {
+    // Dot within the current line
     clock: u16,
-    line_drawn: bool,
+    mode: Mode,
     screen_prepared: bool,
+
+    // Level of the STAT interrupt line
+    stat_line: bool,
+
+    lcd_enabled: bool,
+    // Dots since the last blank frame while the LCD is off
+    off_dots: u32,
+    first_frame: bool,
+
+    // Set when LY matches WY, until the end of the frame
+    window_triggered: bool,
+    // Window row to draw next, which only advances on lines where the window is drawn
+    window_line: u8,
 }
 
 impl State {
     fn new() -> Self {
         State {
             clock: 0,
-            line_drawn: false,
+            mode: Mode::OAMRead,
             screen_prepared: false,
+
+            stat_line: false,
+
+            lcd_enabled: true,
+            off_dots: 0,
+            first_frame: false,
+
+            window_triggered: false,
+            window_line: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ram::Ram;
+    use super::*;
+
+    fn build_bus() -> Ram {
+        let mut bus = Ram::new(vec![0x00; 0x10000]);
+        LCDC.write(&mut bus, 0x93);
+        BGP.write(&mut bus, 0xE4);
+        OBP0.write(&mut bus, 0xE4);
+        // Tile 0 has vertical stripes, tile 1 is solid
+        for i in 0..16 {
+            bus.write8(0x8000 + i, if i % 2 == 0 { 0x55 } else { 0x33 });
+            bus.write8(0x8010 + i, 0xFF);
+        }
+        bus
+    }
+
+    fn add_sprite(bus: &mut Ram, i: u16, x: u8, y: u8) {
+        bus.write8(0xFE00 + i * 4, y);
+        bus.write8(0xFE00 + i * 4 + 1, x);
+        bus.write8(0xFE00 + i * 4 + 2, 0x01);
+    }
+
+    // Dot of the first line at which mode 3 ends
+    fn transfer_end(bus: &mut Ram) -> u16 {
+        let mut ppu = Ppu::new();
+        for dot in 0..ONE_CYCLE {
+            ppu.step(bus, 1);
+            if dot > OAM_SCAN_END && ppu.state.mode == Mode::HBlank {
+                return dot;
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_ppu_transfer_length() {
+        let mut bus = build_bus();
+        assert_eq!(252, transfer_end(&mut bus));
+
+        SCX.write(&mut bus, 0x03);
+        assert_eq!(255, transfer_end(&mut bus));
+        SCX.write(&mut bus, 0x00);
+
+        add_sprite(&mut bus, 0, 8, 16);
+        assert_eq!(252 + 11, transfer_end(&mut bus));
+        add_sprite(&mut bus, 1, 8, 16);
+        assert_eq!(252 + 11 + 6, transfer_end(&mut bus));
+
+        let mut bus = build_bus();
+        LCDC.write(&mut bus, 0xB3);
+        WX.write(&mut bus, 87);
+        assert_eq!(252 + 6, transfer_end(&mut bus));
+    }
+
+    #[test]
+    fn test_ppu_render_modes() {
+        let mut bus = build_bus();
+        SCX.write(&mut bus, 0x05);
+        add_sprite(&mut bus, 0, 4, 16);
+        add_sprite(&mut bus, 1, 50, 12);
+        bus.write8(0xFE07, 0x80);
+        // Overlaps the sprite behind the background, which still hides it
+        add_sprite(&mut bus, 2, 53, 14);
+
+        let mut screens = vec![];
+        for &mode in &[RenderMode::Scanline, RenderMode::PixelFifo] {
+            let mut bus = Ram::new(bus.dump());
+            let mut ppu = Ppu::new();
+            ppu.set_render_mode(mode);
+            while !ppu.is_screen_prepared() {
+                ppu.step(&mut bus, 4);
+            }
+            screens.push(ppu.transfer_screen());
+        }
+
+        for y in 0..SCREEN_H {
+            for x in 0..160 {
+                let (a, b) = (screens[0].get_pixel(x, y), screens[1].get_pixel(x, y));
+                assert_eq!((a.0, a.1, a.2), (b.0, b.1, b.2), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ppu_obj_height_change() {
+        let mut bus = build_bus();
+        LCDC.write(&mut bus, 0x97);
+        // A Y-flipped 8x16 sprite, which becomes 8x8 after it has been picked for line 10
+        add_sprite(&mut bus, 0, 8, 16);
+        bus.write8(0xFE03, 0x40);
+
+        let mut ppu = Ppu::new();
+        while LY.read(&mut bus) != 10 || ppu.state.mode != Mode::VRAMRead {
+            ppu.step(&mut bus, 1);
+        }
+        LCDC.write(&mut bus, 0x93);
+        while !ppu.is_screen_prepared() {
+            ppu.step(&mut bus, 4);
+        }
+
+        // Tile 1 is fetched as is, since its lowest bit only gets cleared for 8x16 sprites
+        let pixel = ppu.transfer_screen().get_pixel(0, 10);
+        assert_eq!(renderer::get_rgb(0xE4, 3), (pixel.0, pixel.1, pixel.2));
+    }
+
+    #[test]
+    fn test_ppu_window_line() {
+        for &mode in &[RenderMode::Scanline, RenderMode::PixelFifo] {
+            let mut bus = build_bus();
+            WX.write(&mut bus, 5);
+            // Blank background, and a window with stripes on its first row of tiles then solid ones
+            for i in 0..0x400 {
+                bus.write8(0x9800 + i, 0x02);
+            }
+            for i in 0x20..0x40 {
+                bus.write8(0x9C00 + i, 0x01);
+            }
+
+            let mut ppu = Ppu::new();
+            ppu.set_render_mode(mode);
+            while !ppu.is_screen_prepared() {
+                // The window is turned off for lines 8 to 15
+                let ly = LY.read(&mut bus);
+                LCDC.write(&mut bus, if (8..16).contains(&ly) { 0xD3 } else { 0xF3 });
+                ppu.step(&mut bus, 4);
+            }
+
+            let screen = ppu.transfer_screen();
+            let color = |x, y| {
+                let pixel = screen.get_pixel(x, y);
+                (pixel.0, pixel.1, pixel.2)
+            };
+            // WX=5 cuts off the first two pixels of the window
+            assert_eq!(renderer::get_rgb(0xE4, 2), color(0, 0), "{:?}", mode);
+            assert_eq!(renderer::get_rgb(0xE4, 0), color(0, 8), "{:?}", mode);
+            // The window resumes with its ninth row, not the seventeenth
+            assert_eq!(renderer::get_rgb(0xE4, 3), color(0, 16), "{:?}", mode);
+            assert_eq!(renderer::get_rgb(0xE4, 3), color(0, 23), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_ppu_lcd_disable() {
+        let mut bus = build_bus();
+        let mut ppu = Ppu::new();
+        while LY.read(&mut bus) != 10 {
+            ppu.step(&mut bus, 4);
+        }
+
+        LCDC.write(&mut bus, 0x13);
+        STAT.write(&mut bus, 0x78);
+        bus.write8(0xFF0F, 0x00);
+        let mut dots = 0;
+        while !ppu.is_screen_prepared() {
+            ppu.step(&mut bus, 4);
+            dots += 4;
+        }
+        assert_eq!(ONE_FRAME, dots);
+        assert_eq!(0, LY.read(&mut bus));
+        assert_eq!(Mode::HBlank, LCDStatus::new(STAT.read(&mut bus)).mode());
+        assert_eq!(0x00, bus.read8(0xFF0F));
+        let (r, g, b) = renderer::get_rgb(0xE4, 0);
+        let pixel = ppu.transfer_screen().get_pixel(80, 72);
+        assert_eq!((r, g, b), (pixel.0, pixel.1, pixel.2));
+
+        // The first line starts in mode 0, and is 4 dots shorter
+        LCDC.write(&mut bus, 0x93);
+        ppu.step(&mut bus, 76);
+        assert_eq!(Mode::HBlank, LCDStatus::new(STAT.read(&mut bus)).mode());
+        ppu.step(&mut bus, 1);
+        assert_eq!(Mode::VRAMRead, LCDStatus::new(STAT.read(&mut bus)).mode());
+        ppu.step(&mut bus, 255);
+        ppu.step(&mut bus, 119);
+        assert_eq!(0, LY.read(&mut bus));
+        ppu.step(&mut bus, 1);
+        assert_eq!(1, LY.read(&mut bus));
+        ppu.step(&mut bus, 1);
+        assert_eq!(Mode::OAMRead, LCDStatus::new(STAT.read(&mut bus)).mode());
+    }
+
+    #[test]
+    fn test_ppu_stat_interrupt() {
+        let mut bus = build_bus();
+        let mut ppu = Ppu::new();
+        LYC.write(&mut bus, 2);
+        STAT.write(&mut bus, 0x48);
+
+        let mut requests = 0;
+        while LY.read(&mut bus) < 3 {
+            ppu.step(&mut bus, 1);
+            if bus.read8(0xFF0F) & 0x02 != 0 {
+                requests += 1;
+                bus.write8(0xFF0F, 0x00);
+            }
+        }
+        // The HBlank of lines 0 and 1, after which the LYC match keeps the line high through line 2
+        assert_eq!(2, requests);
+
+        STAT.write(&mut bus, 0x00);
+        while ppu.state.mode != Mode::VRAMRead {
+            ppu.step(&mut bus, 1);
+        }
+        ppu.on_stat_write(&mut bus);
+        assert_eq!(0x00, bus.read8(0xFF0F));
+        while ppu.state.mode != Mode::HBlank {
+            ppu.step(&mut bus, 1);
         }
+        ppu.on_stat_write(&mut bus);
+        assert_eq!(0x02, bus.read8(0xFF0F));
     }
 }
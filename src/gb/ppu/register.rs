@@ -139,6 +139,10 @@ impl LCDStatus {
         };
     }
 
+    pub fn lyc_coincidence(&self) -> bool {
+        self.0 & 0b0100 != 0
+    }
+
     pub fn set_lyc_coincidence(&mut self, v: bool) {
         self.0 &= 0b1111_1011;
         if v {
@@ -1,6 +1,7 @@
 use super::super::bus::Bus;
 use super::super::screen::{FrameBuffer, Pixel, SCREEN_H, SCREEN_W};
 use super::register::{LCDControl, Register::*};
+use super::sprite::scan_oam;
 
 pub struct Renderer<'a, B: Bus + 'a> {
     frame_buffer: &'a mut FrameBuffer,
@@ -18,7 +19,8 @@ impl<'a, B: Bus + 'a> Renderer<'a, B> {
         }
     }
 
-    pub fn render_scanline(&mut self) {
+    // `window_line` is the row of the window to draw on this line, if it is visible
+    pub fn render_scanline(&mut self, window_line: Option<u8>) {
         self.bgwin_colors = [0; SCREEN_W as usize];
 
         let y = LY.read(self.bus);
@@ -27,8 +29,8 @@ impl<'a, B: Bus + 'a> Renderer<'a, B> {
         if control.bgwin_enabled() {
             self.render_background_scanline(y);
 
-            if control.win_enabled() {
-                self.render_window_scanline(y);
+            if let Some(window_line) = window_line {
+                self.render_window_scanline(y, window_line);
             }
         }
 
@@ -74,22 +76,19 @@ impl<'a, B: Bus + 'a> Renderer<'a, B> {
     }
 
     // TODO: Refactoring (This implementation is almost same as background)
-    fn render_window_scanline(&mut self, y: u8) {
+    fn render_window_scanline(&mut self, y: u8, window_line: u8) {
         let tiles_loc = LCDControl::new(LCDC.read(self.bus)).bgwin_tile_loc();
         let map_loc = LCDControl::new(LCDC.read(self.bus)).win_map_loc();
         let palette = BGP.read(self.bus);
 
-        let window_x = WX.read(self.bus).wrapping_sub(7);
-        let window_y = WY.read(self.bus);
-        if window_y > y || window_x >= SCREEN_W || window_y >= SCREEN_H {
-            return;
-        }
+        // Below 7, WX moves the window partly off the left edge
+        let window_x = WX.read(self.bus) as i16 - 7;
 
-        let y_adjusted = y.wrapping_sub(window_y);
+        let y_adjusted = window_line;
         let tile_row: u16 = y_adjusted as u16 / 8 * 32;
 
-        for x in 0..SCREEN_W {
-            let x_adjusted = x.wrapping_sub(window_x);
+        for x in window_x.max(0) as u8..SCREEN_W {
+            let x_adjusted = (x as i16 - window_x) as u8;
             let tile_col: u16 = x_adjusted as u16 / 8;
 
             let tile_addr = map_loc + tile_row + tile_col;
@@ -114,68 +113,63 @@ impl<'a, B: Bus + 'a> Renderer<'a, B> {
     }
 
     fn render_sprites_scanline(&mut self, y: u8) {
-        let y = y as i16;
-        let tiles_loc = 0x8000;
-        let sprite_height = LCDControl::new(LCDC.read(self.bus)).obj_height() as i16;
+        let sprite_height = LCDControl::new(LCDC.read(self.bus)).obj_height();
 
         let palette0 = OBP0.read(self.bus);
         let palette1 = OBP1.read(self.bus);
 
-        for sprite_n in 0..40 {
-            let offset: u16 = sprite_n * 4;
-
-            let sprite_x = (self.bus.read8(0xFE00 + offset + 1) as i16) - 8;
-            let sprite_y = (self.bus.read8(0xFE00 + offset) as i16) - 16;
-            if sprite_y > y || (sprite_y + sprite_height) <= y {
-                continue;
-            }
+        // Pixels already taken by a sprite with a higher priority, even if it is hidden behind
+        // the background there
+        let mut taken = [false; SCREEN_W as usize];
 
-            let attrs = self.bus.read8(0xFE00 + offset + 3);
-            let palette = if attrs & (1 << 4) != 0 { palette1 } else { palette0 };
-            let x_flip = attrs & (1 << 5) != 0;
-            let y_flip = attrs & (1 << 6) != 0;
-            let priority = attrs & (1 << 7) != 0;
-
-            let tile_y = if y_flip {
-                sprite_height - 1 - y - sprite_y
-            } else {
-                y - sprite_y
-            };
-
-            let tile_n = self.bus.read8(0xFE00 + offset + 2) as u16;
-            let tile_loc = tiles_loc + (tile_n * 16) + (tile_y * 2) as u16;
+        for sprite in scan_oam(self.bus, y, sprite_height) {
+            let palette = if sprite.uses_obp1() { palette1 } else { palette0 };
+            let tile_loc = sprite.tile_row_addr(y, sprite_height);
 
             let byte1 = self.bus.read8(tile_loc);
             let byte2 = self.bus.read8(tile_loc + 1);
 
             for tile_x in 0..8 {
-                let x = sprite_x + tile_x;
-                if x < 0 || SCREEN_W as i16 <= x {
+                let x = sprite.x as i16 - 8 + tile_x as i16;
+                if x < 0 || SCREEN_W as i16 <= x || taken[x as usize] {
                     continue;
                 }
 
-                let color_bit = if x_flip { tile_x } else { 7 - tile_x };
-                let color_n = get_color_number(color_bit as u8, byte1, byte2);
+                let color_bit = if sprite.x_flip() { tile_x } else { 7 - tile_x };
+                let color_n = get_color_number(color_bit, byte1, byte2);
                 if color_n == 0 {
                     continue;
                 }
+                taken[x as usize] = true;
 
                 let (r, g, b) = get_rgb(palette, color_n);
-                if !priority || self.bgwin_colors[x as usize] == 0 {
-                    self.frame_buffer.set_pixel(x as u8, y as u8, Pixel(r, g, b, 255));
+                if !sprite.behind_bg() || self.bgwin_colors[x as usize] == 0 {
+                    self.frame_buffer.set_pixel(x as u8, y, Pixel(r, g, b, 255));
                 }
             }
         }
     }
 }
 
-fn get_color_number(bit: u8, byte1: u8, byte2: u8) -> u8 {
+// What the LCD shows while it is off, or for the first frame after it is turned back on
+pub fn blank_frame() -> FrameBuffer {
+    let (r, g, b) = PALETTE[0];
+    let mut frame = FrameBuffer::new();
+    for y in 0..SCREEN_H {
+        for x in 0..SCREEN_W {
+            frame.set_pixel(x, y, Pixel(r, g, b, 255));
+        }
+    }
+    frame
+}
+
+pub fn get_color_number(bit: u8, byte1: u8, byte2: u8) -> u8 {
     let lo = (byte1 & (1 << bit) != 0) as u8;
     let hi = (byte2 & (1 << bit) != 0) as u8;
     (hi << 1) | lo
 }
 
-fn get_rgb(palette: u8, color_n: u8) -> (u8, u8, u8) {
+pub fn get_rgb(palette: u8, color_n: u8) -> (u8, u8, u8) {
     let color = (palette >> (color_n * 2)) & 0b11;
     PALETTE[color as usize]
 }
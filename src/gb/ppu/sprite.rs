@@ -0,0 +1,99 @@
+use super::super::bus::Bus;
+
+const OAM_ADDR: u16 = 0xFE00;
+const OAM_ENTRIES: u16 = 40;
+// The OAM scan stops looking once it has found this many sprites
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sprite {
+    // Raw OAM coordinates, which are offset by (8, 16) from the screen
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attrs: u8,
+}
+
+impl Sprite {
+    pub fn behind_bg(&self) -> bool {
+        self.attrs & (1 << 7) != 0
+    }
+
+    pub fn y_flip(&self) -> bool {
+        self.attrs & (1 << 6) != 0
+    }
+
+    pub fn x_flip(&self) -> bool {
+        self.attrs & (1 << 5) != 0
+    }
+
+    pub fn uses_obp1(&self) -> bool {
+        self.attrs & (1 << 4) != 0
+    }
+
+    // Address of the tile data for line `ly`, which the sprite has to overlap. The height can shrink
+    // after the OAM scan, when LCDC changes in mode 3, so the row is wrapped to the current height.
+    pub fn tile_row_addr(&self, ly: u8, height: u8) -> u16 {
+        let mut row = ly.wrapping_add(16).wrapping_sub(self.y) & (height - 1);
+        if self.y_flip() {
+            row = height - 1 - row;
+        }
+        // The lowest bit of the tile number is ignored for 8x16 sprites
+        let tile = if height == 16 { self.tile & 0xFE } else { self.tile };
+        0x8000 + tile as u16 * 16 + row as u16 * 2
+    }
+}
+
+// The first sprites in OAM overlapping line `ly`, ordered by drawing priority: on overlap the sprite
+// with the lower X wins, and then the one earlier in OAM
+pub fn scan_oam<B: Bus>(bus: &mut B, ly: u8, height: u8) -> Vec<Sprite> {
+    let mut sprites = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+    for i in 0..OAM_ENTRIES {
+        let addr = OAM_ADDR + i * 4;
+        let sprite = Sprite {
+            y: bus.read8(addr),
+            x: bus.read8(addr + 1),
+            tile: bus.read8(addr + 2),
+            attrs: bus.read8(addr + 3),
+        };
+
+        // Sprites off the screen horizontally still count towards the limit
+        let top = sprite.y as i16 - 16;
+        if top <= ly as i16 && (ly as i16) < top + height as i16 {
+            sprites.push(sprite);
+            if sprites.len() == MAX_SPRITES_PER_LINE {
+                break;
+            }
+        }
+    }
+    // The sort is stable, so OAM order is kept for sprites at the same X
+    sprites.sort_by_key(|s| s.x);
+    sprites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::ram::Ram;
+    use super::*;
+
+    #[test]
+    fn test_scan_oam() {
+        let mut bus = Ram::new(vec![0x00; 0x10000]);
+        // 12 sprites on lines 0-7, the last two of which are dropped
+        for i in 0..12 {
+            bus.write8(OAM_ADDR + i * 4, 16);
+            bus.write8(OAM_ADDR + i * 4 + 1, 100 - i as u8 * 5);
+            bus.write8(OAM_ADDR + i * 4 + 2, i as u8);
+        }
+        bus.write8(OAM_ADDR + 1, 60);
+        bus.write8(OAM_ADDR + 4 * 2 + 1, 60);
+
+        let sprites = scan_oam(&mut bus, 4, 8);
+        assert_eq!(10, sprites.len());
+        let tiles: Vec<u8> = sprites.iter().map(|s| s.tile).collect();
+        assert_eq!(vec![9, 0, 2, 8, 7, 6, 5, 4, 3, 1], tiles);
+
+        assert!(scan_oam(&mut bus, 8, 8).is_empty());
+        assert_eq!(10, scan_oam(&mut bus, 12, 16).len());
+    }
+}
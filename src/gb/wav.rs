@@ -0,0 +1,76 @@
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const HEADER_SIZE: usize = 44;
+
+// Records interleaved stereo samples, and turns them into a 16-bit PCM WAV file
+pub struct WavRecorder {
+    sample_rate: u32,
+    data: Vec<u8>,
+}
+
+impl WavRecorder {
+    pub fn new(sample_rate: u32) -> Self {
+        WavRecorder {
+            sample_rate,
+            data: vec![],
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        self.data.reserve(samples.len() * 2);
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // Number of left and right sample pairs recorded so far
+    pub fn frames(&self) -> usize {
+        self.data.len() / (CHANNELS * BITS_PER_SAMPLE / 8) as usize
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        let byte_rate = self.sample_rate * block_align as u32;
+        let data_size = self.data.len() as u32;
+
+        let mut wav = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(HEADER_SIZE as u32 - 8 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&CHANNELS.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&byte_rate.to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        wav.extend_from_slice(&self.data);
+        wav
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wav_recorder() {
+        let mut recorder = WavRecorder::new(48_000);
+        recorder.push(&[0.0, 1.0, -1.0, 2.0]);
+        assert_eq!(2, recorder.frames());
+
+        let wav = recorder.finish();
+        assert_eq!(HEADER_SIZE + 8, wav.len());
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(&[44, 0, 0, 0], &wav[4..8]);
+        assert_eq!(&[0x80, 0xBB, 0x00, 0x00], &wav[24..28]);
+        assert_eq!(&[8, 0, 0, 0], &wav[40..44]);
+        assert_eq!(&[0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F], &wav[44..]);
+    }
+}
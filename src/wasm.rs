@@ -1,20 +1,28 @@
+#![recursion_limit = "1024"]
+
 #[macro_use]
 extern crate stdweb;
 
 mod gb;
 
-use self::gb::cartridge::Cartridge;
+use self::gb::archive;
+use self::gb::cartridge::{Cartridge, RtcMode};
 use self::gb::joypad::Button;
+use self::gb::patch;
 use self::gb::screen::{SCREEN_H, SCREEN_W};
 use self::gb::GameBoy;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use stdweb::traits::*;
 use stdweb::unstable::TryInto;
 use stdweb::web;
-use stdweb::web::event::{ChangeEvent, KeyDownEvent, KeyUpEvent, ProgressLoadEvent};
+use stdweb::web::event::{ChangeEvent, ClickEvent, InputEvent, KeyDownEvent, KeyUpEvent, ProgressLoadEvent};
 use stdweb::web::html_element::{CanvasElement, InputElement};
-use stdweb::web::{document, CanvasRenderingContext2d, FileList, FileReader, FileReaderResult};
+use stdweb::web::{document, CanvasRenderingContext2d, FileList, FileReader, FileReaderResult, TypedArray};
+use stdweb::Value;
+
+// Animation frames between writes of dirty save RAM to localStorage, about a second as in the CLI
+const SAVE_INTERVAL: u32 = 60;
 
 macro_rules! enclose {
     ([$($x: ident), *] $y: expr) => {
@@ -22,34 +30,168 @@ macro_rules! enclose {
     }
 }
 
-fn handle_custom_rom(gameboy: Rc<RefCell<GameBoy>>) {
+fn handle_custom_rom(gameboy: Rc<RefCell<GameBoy>>, save_key: Rc<RefCell<Option<String>>>) {
     let load_rom_button = web::document().get_element_by_id("load-rom").unwrap();
     load_rom_button.add_event_listener(move |event: ChangeEvent| {
         let input: InputElement = event.target().unwrap().try_into().unwrap();
         let files: FileList = js!( return @{input}.files; ).try_into().unwrap();
+
+        // An IPS, UPS or BPS patch can be picked together with the ROM
+        let (patches, roms): (Vec<_>, Vec<_>) = files.iter().partition(|file| patch::is_patch_file_name(&file.name()));
+        let rom_file = match roms.into_iter().next() {
+            Some(file) => file,
+            None => return,
+        };
+        let patch_file = patches.into_iter().next();
+
+        // Patched games may not share the original's save layout, so they get their own key
+        let key = format!("sav:{}", patch_file.as_ref().unwrap_or(&rom_file).name());
+
+        read_file(
+            &rom_file,
+            enclose!([gameboy, save_key] move |rom: Vec<u8>| {
+                let rom = match archive::extract_rom(rom, None) {
+                    Ok(rom) => rom,
+                    Err(err) => return show_rom_info(&format!("Failed to extract the ROM: {}", err)),
+                };
+
+                let patch_file = match patch_file {
+                    Some(ref file) => file,
+                    None => return load_rom(&gameboy, &save_key, key, rom),
+                };
+
+                read_file(patch_file, move |data: Vec<u8>| match patch::apply(&rom, &data) {
+                    Ok(rom) => load_rom(&gameboy, &save_key, key, rom),
+                    Err(err) => show_rom_info(&format!("Failed to apply the patch: {}", err)),
+                });
+            }),
+        );
+    });
+}
+
+fn load_rom(gameboy: &Rc<RefCell<GameBoy>>, save_key: &Rc<RefCell<Option<String>>>, key: String, rom: Vec<u8>) {
+    let mut cart = match Cartridge::new(rom) {
+        Ok(cart) => cart,
+        Err(err) => {
+            show_rom_info(&format!("Failed to load the ROM: {}", err));
+            return;
+        }
+    };
+    show_rom_info(&cart.header().to_string());
+    cart.set_rtc_mode(RtcMode::Host(host_time));
+    gameboy.borrow_mut().pause();
+    gameboy.borrow_mut().load(cart);
+    if let Some(data) = web::window().local_storage().get(&key) {
+        gameboy.borrow_mut().import_ram(&decode_hex(&data));
+    }
+    *save_key.borrow_mut() = Some(key);
+    gameboy.borrow_mut().unpause();
+}
+
+fn show_rom_info(text: &str) {
+    let rom_info = web::document().get_element_by_id("rom-info").unwrap();
+    rom_info.set_text_content(text);
+}
+
+fn read_file<B: IBlob, F: FnOnce(Vec<u8>) + 'static>(file: &B, callback: F) {
+    let reader = FileReader::new();
+    let mut callback = Some(callback);
+    reader.add_event_listener(enclose!([reader] move |_: ProgressLoadEvent| {
+        let data: Vec<u8> = match reader.result().unwrap() {
+            FileReaderResult::ArrayBuffer(buffer) => buffer,
+            _ => unreachable!(),
+        }
+        .into();
+
+        if let Some(callback) = callback.take() {
+            callback(data);
+        }
+    }));
+
+    reader.read_as_array_buffer(file).unwrap();
+}
+
+fn handle_save_ram(gameboy: Rc<RefCell<GameBoy>>, save_key: Rc<RefCell<Option<String>>>) {
+    let load_sav_button = web::document().get_element_by_id("load-sav").unwrap();
+    load_sav_button.add_event_listener(enclose!([gameboy, save_key] move |event: ChangeEvent| {
+        let input: InputElement = event.target().unwrap().try_into().unwrap();
+        let files: FileList = js!( return @{input}.files; ).try_into().unwrap();
         let file = match files.iter().next() {
             Some(file) => file,
             None => return,
         };
 
         let reader = FileReader::new();
-        reader.add_event_listener(enclose!([gameboy, reader] move |_: ProgressLoadEvent| {
-            let rom: Vec<u8> = match reader.result().unwrap() {
+        reader.add_event_listener(enclose!([gameboy, save_key, reader] move |_: ProgressLoadEvent| {
+            let data: Vec<u8> = match reader.result().unwrap() {
                 FileReaderResult::ArrayBuffer(buffer) => buffer,
                 _ => unreachable!(),
             }
             .into();
 
-            let cart = Cartridge::new(rom);
-            gameboy.borrow_mut().pause();
-            gameboy.borrow_mut().load(cart);
-            gameboy.borrow_mut().unpause();
+            let mut gameboy = gameboy.borrow_mut();
+            gameboy.import_ram(&data);
+            // The game may never write RAM again, so the imported save has to be stored right away
+            store_save_ram(&mut gameboy, &save_key.borrow());
         }));
 
         reader.read_as_array_buffer(&file).unwrap();
+    }));
+
+    let export_sav_button = web::document().get_element_by_id("export-sav").unwrap();
+    export_sav_button.add_event_listener(move |_: ClickEvent| {
+        // Exporting clears the dirty flag, so pending changes are stored first
+        let mut gameboy = gameboy.borrow_mut();
+        flush_save_ram(&mut gameboy, &save_key.borrow());
+        let data = match gameboy.export_ram() {
+            Some(data) => data,
+            None => return,
+        };
+
+        js! {
+            let blob = new Blob([Uint8Array.from(@{data})], { type: "application/octet-stream" });
+            let a = document.createElement("a");
+            a.href = URL.createObjectURL(blob);
+            a.download = "game.sav";
+            a.click();
+            URL.revokeObjectURL(a.href);
+        }
     });
 }
 
+fn flush_save_ram(gameboy: &mut GameBoy, save_key: &Option<String>) {
+    if gameboy.is_ram_dirty() {
+        store_save_ram(gameboy, save_key);
+    }
+}
+
+fn store_save_ram(gameboy: &mut GameBoy, save_key: &Option<String>) {
+    let key = match save_key {
+        Some(key) => key,
+        None => return,
+    };
+
+    if let Some(data) = gameboy.export_ram() {
+        let _ = web::window().local_storage().insert(key, &encode_hex(&data));
+    }
+}
+
+// localStorage only holds strings, so save data is stored hex-encoded
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(s: &str) -> Vec<u8> {
+    (0..s.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
+
+fn host_time() -> u64 {
+    let secs: f64 = js!( return Date.now() / 1000; ).try_into().unwrap();
+    secs as u64
+}
+
 fn handle_input(gameboy: Rc<RefCell<GameBoy>>) {
     let handler = |key: &str| -> Option<Button> {
         match key.to_lowercase().as_ref() {
@@ -68,21 +210,28 @@ fn handle_input(gameboy: Rc<RefCell<GameBoy>>) {
         }
     };
 
-    web::window().add_event_listener(enclose!([gameboy] move |event: KeyDownEvent| {
+    // Arrow keys also tilt the cartridge, for the ones with an accelerometer (MBC7)
+    let tilt = Rc::new(RefCell::new(Tilt::new()));
+
+    web::window().add_event_listener(enclose!([gameboy, tilt] move |event: KeyDownEvent| {
         if event.repeat() {
             return;
         }
 
         match handler(&event.key()) {
             Some(button) => {
+                let (x, y) = tilt.borrow_mut().update(&button, true);
+                gameboy.borrow_mut().set_tilt(x, y);
                 gameboy.borrow_mut().press(button);
             }
             None => (),
         }
     }));
-    web::window().add_event_listener(enclose!([gameboy] move |event: KeyUpEvent| {
+    web::window().add_event_listener(enclose!([gameboy, tilt] move |event: KeyUpEvent| {
         match handler(&event.key()) {
             Some(button) => {
+                let (x, y) = tilt.borrow_mut().update(&button, false);
+                gameboy.borrow_mut().set_tilt(x, y);
                 gameboy.borrow_mut().release(button);
             }
             None => (),
@@ -90,19 +239,236 @@ fn handle_input(gameboy: Rc<RefCell<GameBoy>>) {
     }));
 }
 
-fn async_render_loop(ctx: CanvasRenderingContext2d, gameboy: Rc<RefCell<GameBoy>>) {
+struct Tilt {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+}
+
+impl Tilt {
+    fn new() -> Self {
+        Tilt {
+            up: false,
+            down: false,
+            left: false,
+            right: false,
+        }
+    }
+
+    fn update(&mut self, button: &Button, pressed: bool) -> (f32, f32) {
+        match button {
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::Left => self.left = pressed,
+            Button::Right => self.right = pressed,
+            _ => (),
+        };
+
+        let axis = |neg: bool, pos: bool| (pos as i8 - neg as i8) as f32;
+        (axis(self.left, self.right), axis(self.up, self.down))
+    }
+}
+
+// Sample frames per ScriptProcessorNode callback
+const AUDIO_BUFFER_SIZE: u32 = 2048;
+// Playback (re)starts once this many sample frames are queued
+const AUDIO_PREBUFFER: u32 = AUDIO_BUFFER_SIZE * 2;
+// Beyond this, the oldest samples are dropped to keep the latency down
+const AUDIO_MAX_QUEUED: u32 = AUDIO_BUFFER_SIZE * 6;
+// Upper bound on the frames emulated in one audio callback, so a stalled tab cannot freeze the page
+const MAX_REFILL_FRAMES: u32 = 8;
+
+// Audio output through a ScriptProcessorNode, playing from a queue of interleaved stereo samples
+struct Audio {
+    handle: Value,
+}
+
+impl Audio {
+    fn new() -> Self {
+        let handle = js! {
+            let AudioContext = window.AudioContext || window.webkitAudioContext;
+            let context = new AudioContext();
+            let gain = context.createGain();
+            gain.connect(context.destination);
+            let node = context.createScriptProcessor(@{AUDIO_BUFFER_SIZE}, 0, 2);
+            node.connect(gain);
+
+            let audio = {
+                context: context,
+                gain: gain,
+                node: node,
+                chunks: [],
+                offset: 0,
+                queued: 0,
+                playing: false,
+                refill: null,
+            };
+
+            audio.push = function(samples) {
+                if (samples.length === 0) {
+                    return;
+                }
+                audio.chunks.push(samples);
+                audio.queued += samples.length / 2;
+                while (audio.queued > @{AUDIO_MAX_QUEUED} && audio.chunks.length > 1) {
+                    let dropped = audio.chunks.shift();
+                    audio.queued -= (dropped.length - audio.offset) / 2;
+                    audio.offset = 0;
+                }
+            };
+
+            node.onaudioprocess = function(event) {
+                let left = event.outputBuffer.getChannelData(0);
+                let right = event.outputBuffer.getChannelData(1);
+                if (audio.refill !== null && audio.queued < @{AUDIO_PREBUFFER}) {
+                    let samples = audio.refill(@{AUDIO_PREBUFFER} - audio.queued);
+                    audio.push(samples);
+                }
+                if (audio.queued >= @{AUDIO_PREBUFFER}) {
+                    audio.playing = true;
+                }
+
+                for (let i = 0; i < left.length; i++) {
+                    // On a buffer underrun, play silence until the queue has filled up again
+                    if (!audio.playing || audio.chunks.length === 0) {
+                        audio.playing = false;
+                        left[i] = 0;
+                        right[i] = 0;
+                        continue;
+                    }
+
+                    let chunk = audio.chunks[0];
+                    left[i] = chunk[audio.offset];
+                    right[i] = chunk[audio.offset + 1];
+                    audio.offset += 2;
+                    audio.queued -= 1;
+                    if (audio.offset >= chunk.length) {
+                        audio.chunks.shift();
+                        audio.offset = 0;
+                    }
+                }
+            };
+
+            return audio;
+        };
+        Audio { handle }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        let rate: f64 = js!( return @{&self.handle}.context.sampleRate; ).try_into().unwrap();
+        rate as u32
+    }
+
+    fn push(&self, samples: &[f32]) {
+        let samples = TypedArray::<f32>::from(samples);
+        js! { @{&self.handle}.push(@{samples}); }
+    }
+
+    fn set_volume(&self, volume: f32) {
+        js! { @{&self.handle}.gain.gain.value = @{volume}; }
+    }
+
+    // Browsers only let audio start after a user gesture
+    fn resume(&self) {
+        js! {
+            let context = @{&self.handle}.context;
+            if (context.state === "suspended") {
+                context.resume();
+            }
+        }
+    }
+
+    // `refill` is called with the number of sample frames missing from the queue
+    fn set_refill<F: FnMut(u32) -> TypedArray<f32> + 'static>(&self, refill: F) {
+        js! {
+            let refill = @{stdweb::Mut(refill)};
+            @{&self.handle}.refill = function(needed) {
+                return refill(needed);
+            };
+        }
+    }
+}
+
+fn handle_audio(gameboy: Rc<RefCell<GameBoy>>, screen: Rc<RefCell<Vec<u8>>>, audio_sync: Rc<Cell<bool>>) -> Rc<Audio> {
+    let audio = Rc::new(Audio::new());
+    gameboy.borrow_mut().set_sample_rate(audio.sample_rate());
+
+    // When synced to audio, frames are emulated whenever the audio queue runs low
+    audio.set_refill(enclose!([audio_sync] move |needed: u32| {
+        let mut samples = vec![];
+        if audio_sync.get() {
+            for _ in 0..MAX_REFILL_FRAMES {
+                if samples.len() / 2 >= needed as usize {
+                    break;
+                }
+                *screen.borrow_mut() = gameboy.borrow_mut().step();
+                let frame = gameboy.borrow_mut().take_samples();
+                // Nothing is produced while paused
+                if frame.is_empty() {
+                    break;
+                }
+                samples.extend(frame);
+            }
+        }
+        TypedArray::from(&samples[..])
+    }));
+
+    web::window().add_event_listener(enclose!([audio] move |_: ClickEvent| audio.resume()));
+    web::window().add_event_listener(enclose!([audio] move |_: KeyDownEvent| audio.resume()));
+
+    let volume: InputElement = document().get_element_by_id("volume").unwrap().try_into().unwrap();
+    let mute: InputElement = document().get_element_by_id("mute").unwrap().try_into().unwrap();
+    let update_volume = enclose!([audio, volume, mute] move || {
+        let muted: bool = js!( return @{&mute}.checked; ).try_into().unwrap();
+        let level = volume.raw_value().parse::<f32>().unwrap_or(0.0) / 100.0;
+        audio.set_volume(if muted { 0.0 } else { level });
+    });
+    update_volume();
+    volume.add_event_listener(enclose!([update_volume] move |_: InputEvent| update_volume()));
+    mute.add_event_listener(move |_: ChangeEvent| update_volume());
+
+    let sync: InputElement = document().get_element_by_id("audio-sync").unwrap().try_into().unwrap();
+    sync.add_event_listener(move |event: ChangeEvent| {
+        let input: InputElement = event.target().unwrap().try_into().unwrap();
+        let checked: bool = js!( return @{input}.checked; ).try_into().unwrap();
+        audio_sync.set(checked);
+    });
+
+    audio
+}
+
+fn async_render_loop(
+    ctx: CanvasRenderingContext2d,
+    gameboy: Rc<RefCell<GameBoy>>,
+    save_key: Rc<RefCell<Option<String>>>,
+    audio: Rc<Audio>,
+    screen: Rc<RefCell<Vec<u8>>>,
+    audio_sync: Rc<Cell<bool>>,
+    save_frames: u32,
+) {
     web::window().request_animation_frame(move |_| {
-        let screen = gameboy.borrow_mut().step();
+        // Otherwise, the audio callback drives the emulation and only the latest frame is drawn here
+        if !audio_sync.get() {
+            *screen.borrow_mut() = gameboy.borrow_mut().step();
+            audio.push(&gameboy.borrow_mut().take_samples());
+        }
+        let save_frames = if save_frames + 1 >= SAVE_INTERVAL {
+            flush_save_ram(&mut gameboy.borrow_mut(), &save_key.borrow());
+            0
+        } else {
+            save_frames + 1
+        };
 
         js! {
             @{&ctx}.putImageData(new ImageData(
-                Uint8ClampedArray.from(@{screen}),
+                Uint8ClampedArray.from(@{screen.borrow().clone()}),
                 @{SCREEN_W},
                 @{SCREEN_H},
             ), 0, 0);
         }
 
-        async_render_loop(ctx, gameboy);
+        async_render_loop(ctx, gameboy, save_key, audio, screen, audio_sync, save_frames);
     });
 }
 
@@ -110,8 +476,13 @@ fn main() {
     stdweb::initialize();
 
     let gameboy = Rc::new(RefCell::new(GameBoy::new()));
-    handle_custom_rom(gameboy.clone());
+    let save_key = Rc::new(RefCell::new(None));
+    handle_custom_rom(gameboy.clone(), save_key.clone());
+    handle_save_ram(gameboy.clone(), save_key.clone());
     handle_input(gameboy.clone());
+    let screen = Rc::new(RefCell::new(gameboy.borrow_mut().step()));
+    let audio_sync = Rc::new(Cell::new(false));
+    let audio = handle_audio(gameboy.clone(), screen.clone(), audio_sync.clone());
 
     let canvas: CanvasElement = document()
         .query_selector("canvas")
@@ -120,7 +491,7 @@ fn main() {
         .try_into()
         .unwrap();
     let ctx: CanvasRenderingContext2d = canvas.get_context().unwrap();
-    async_render_loop(ctx, gameboy.clone());
+    async_render_loop(ctx, gameboy.clone(), save_key, audio, screen, audio_sync, 0);
 
     stdweb::event_loop();
 }